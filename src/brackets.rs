@@ -0,0 +1,111 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+use crate::theme;
+
+const BRACKET_PAIRS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}"), ("<", ">")];
+
+/// Colors bracket pairs by their nesting depth with the rainbow palette. Brackets are the
+/// anonymous tokens of the parse tree, paired with a sibling of the matching kind, so brackets in
+/// strings and comments or `<` used as an operator are not counted.
+pub fn keys(tree: &Tree, palette_len: usize) -> Vec<(Range<usize>, String)> {
+    let mut keys = vec![];
+    visit(tree.root_node(), 0, palette_len, &mut keys);
+    keys.sort_by_key(|(range, _)| range.start);
+    keys
+}
+
+fn visit(node: Node, depth: usize, palette_len: usize, keys: &mut Vec<(Range<usize>, String)>) {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.children(&mut cursor).collect();
+
+    // pair the brackets among the children first, unmatched ones are not brackets
+    let mut is_paired = vec![false; children.len()];
+    let mut open: Vec<(usize, &str)> = vec![];
+    for (index, child) in children.iter().enumerate() {
+        if child.is_named() {
+            continue;
+        }
+        if let Some((_, close)) = BRACKET_PAIRS.iter().find(|(o, _)| *o == child.kind()) {
+            open.push((index, close));
+        } else if BRACKET_PAIRS.iter().any(|(_, c)| *c == child.kind()) {
+            if let Some(position) = open.iter().rposition(|(_, close)| *close == child.kind()) {
+                is_paired[open[position].0] = true;
+                is_paired[index] = true;
+                open.truncate(position);
+            }
+        }
+    }
+
+    let mut depth = depth;
+    for (child, is_paired) in children.into_iter().zip(is_paired) {
+        match is_paired {
+            true if BRACKET_PAIRS.iter().any(|(o, _)| *o == child.kind()) => {
+                keys.push((child.byte_range(), theme::rainbow_key(depth, palette_len)));
+                depth += 1;
+            }
+            true => {
+                depth -= 1;
+                keys.push((child.byte_range(), theme::rainbow_key(depth, palette_len)));
+            }
+            false => visit(child, depth, palette_len, keys),
+        }
+    }
+}
+
+// the tests need a grammar, which is only available without setup when it is bundled
+#[cfg(all(test, feature = "bundled-grammars"))]
+mod tests {
+    use crate::ts;
+
+    /// The keys as `(text, key)` pairs
+    fn keys(code: &str, palette_len: usize) -> Vec<(&str, String)> {
+        let tree = ts::parse(tree_sitter_rust::language(), code).unwrap();
+        super::keys(&tree, palette_len)
+            .into_iter()
+            .map(|(range, key)| (&code[range], key))
+            .collect()
+    }
+
+    #[test]
+    fn colors_pairs_by_depth() {
+        let expected = [
+            ("(", "rainbow.1"),
+            ("[", "rainbow.2"),
+            ("]", "rainbow.2"),
+            (")", "rainbow.1"),
+            ("(", "rainbow.1"),
+            (")", "rainbow.1"),
+        ];
+        assert_eq!(
+            keys("f([1]); g();", 3),
+            expected.map(|(text, key)| (text, key.to_string()))
+        );
+    }
+
+    #[test]
+    fn cycles_through_the_palette() {
+        let expected = [
+            ("(", "rainbow.1"),
+            ("(", "rainbow.2"),
+            ("(", "rainbow.1"),
+            (")", "rainbow.1"),
+            (")", "rainbow.2"),
+            (")", "rainbow.1"),
+        ];
+        assert_eq!(
+            keys("f(g(h()));", 2),
+            expected.map(|(text, key)| (text, key.to_string()))
+        );
+    }
+
+    #[test]
+    fn ignores_brackets_in_strings_and_operators() {
+        let expected = [("(", "rainbow.1"), (")", "rainbow.1")];
+        assert_eq!(
+            keys(r#"f("(", a < b);"#, 3),
+            expected.map(|(text, key)| (text, key.to_string()))
+        );
+    }
+}
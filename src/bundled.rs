@@ -0,0 +1,180 @@
+use std::path::Path;
+
+use tree_sitter::Language;
+
+/// A grammar compiled into the binary with the `bundled-grammars` feature. Queries found in the
+/// query search dirs take precedence over the embedded ones.
+pub struct BundledGrammar {
+    /// Name of the query directory, like the scope of loader grammars without `source.`
+    pub name: &'static str,
+    pub file_types: &'static [&'static str],
+    pub language: fn() -> Language,
+    /// Highlights, injection and locals queries, each made up of parts which are joined
+    /// together. They are used as the grammar crates ship them, without `query::process`, so the
+    /// queries of the derived language come before those of the base language to take
+    /// precedence with tree-sitter-highlight's first matching pattern.
+    pub queries: [&'static [&'static str]; 3],
+    /// Tags query used by `outline`, made up of parts in the same way. It is empty if the grammar
+    /// crate does not export one.
+    pub tags_query: &'static [&'static str],
+}
+
+impl BundledGrammar {
+    pub fn queries(&self) -> [String; 3] {
+        self.queries.map(|parts| parts.join("\n"))
+    }
+
+    pub fn tags_query(&self) -> String {
+        self.tags_query.join("\n")
+    }
+}
+
+#[cfg(feature = "bundled-grammars")]
+pub const GRAMMARS: &[BundledGrammar] = &[
+    BundledGrammar {
+        name: "rust",
+        file_types: &["rs"],
+        language: tree_sitter_rust::language,
+        queries: [
+            &[tree_sitter_rust::HIGHLIGHT_QUERY],
+            &[tree_sitter_rust::INJECTIONS_QUERY],
+            &[],
+        ],
+        tags_query: &[tree_sitter_rust::TAGGING_QUERY],
+    },
+    BundledGrammar {
+        name: "c",
+        file_types: &["c", "h"],
+        language: tree_sitter_c::language,
+        queries: [&[tree_sitter_c::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[tree_sitter_c::TAGS_QUERY],
+    },
+    BundledGrammar {
+        name: "cpp",
+        file_types: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        language: tree_sitter_cpp::language,
+        queries: [
+            &[
+                tree_sitter_cpp::HIGHLIGHT_QUERY,
+                tree_sitter_c::HIGHLIGHT_QUERY,
+            ],
+            &[],
+            &[],
+        ],
+        tags_query: &[tree_sitter_cpp::TAGS_QUERY],
+    },
+    BundledGrammar {
+        name: "python",
+        file_types: &["py", "pyi"],
+        language: tree_sitter_python::language,
+        queries: [&[tree_sitter_python::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[tree_sitter_python::TAGGING_QUERY],
+    },
+    BundledGrammar {
+        name: "javascript",
+        file_types: &["js", "mjs", "cjs", "jsx"],
+        language: tree_sitter_javascript::language,
+        queries: [
+            &[
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+            ],
+            &[tree_sitter_javascript::INJECTION_QUERY],
+            &[tree_sitter_javascript::LOCALS_QUERY],
+        ],
+        tags_query: &[tree_sitter_javascript::TAGGING_QUERY],
+    },
+    BundledGrammar {
+        name: "typescript",
+        file_types: &["ts", "mts", "cts"],
+        language: tree_sitter_typescript::language_typescript,
+        queries: [
+            &[
+                tree_sitter_typescript::HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+            ],
+            &[tree_sitter_javascript::INJECTION_QUERY],
+            &[
+                tree_sitter_typescript::LOCALS_QUERY,
+                tree_sitter_javascript::LOCALS_QUERY,
+            ],
+        ],
+        tags_query: &[
+            tree_sitter_typescript::TAGGING_QUERY,
+            tree_sitter_javascript::TAGGING_QUERY,
+        ],
+    },
+    BundledGrammar {
+        name: "tsx",
+        file_types: &["tsx"],
+        language: tree_sitter_typescript::language_tsx,
+        queries: [
+            &[
+                tree_sitter_typescript::HIGHLIGHT_QUERY,
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+            ],
+            &[tree_sitter_javascript::INJECTION_QUERY],
+            &[
+                tree_sitter_typescript::LOCALS_QUERY,
+                tree_sitter_javascript::LOCALS_QUERY,
+            ],
+        ],
+        tags_query: &[
+            tree_sitter_typescript::TAGGING_QUERY,
+            tree_sitter_javascript::TAGGING_QUERY,
+        ],
+    },
+    BundledGrammar {
+        name: "go",
+        file_types: &["go"],
+        language: tree_sitter_go::language,
+        queries: [&[tree_sitter_go::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[tree_sitter_go::TAGGING_QUERY],
+    },
+    BundledGrammar {
+        name: "java",
+        file_types: &["java"],
+        language: tree_sitter_java::language,
+        queries: [&[tree_sitter_java::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[tree_sitter_java::TAGGING_QUERY],
+    },
+    BundledGrammar {
+        name: "json",
+        file_types: &["json"],
+        language: tree_sitter_json::language,
+        queries: [&[tree_sitter_json::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[],
+    },
+    BundledGrammar {
+        name: "toml",
+        file_types: &["toml"],
+        language: tree_sitter_toml::language,
+        queries: [&[tree_sitter_toml::HIGHLIGHT_QUERY], &[], &[]],
+        tags_query: &[],
+    },
+    BundledGrammar {
+        name: "markdown",
+        file_types: &["md", "markdown"],
+        language: tree_sitter_md::language,
+        queries: [&[tree_sitter_md::HIGHLIGHTS_QUERY], &[], &[]],
+        tags_query: &[],
+    },
+];
+
+#[cfg(not(feature = "bundled-grammars"))]
+pub const GRAMMARS: &[BundledGrammar] = &[];
+
+/// Finds the bundled grammar for a file by its name or extension, like the loader does
+pub fn for_file_name(file_name: &Path) -> Option<&'static BundledGrammar> {
+    let matches = |file_type: &str| {
+        file_name.file_name().and_then(|name| name.to_str()) == Some(file_type)
+            || file_name.extension().and_then(|ext| ext.to_str()) == Some(file_type)
+    };
+    GRAMMARS.iter().find(|grammar| {
+        grammar
+            .file_types
+            .iter()
+            .any(|file_type| matches(file_type))
+    })
+}
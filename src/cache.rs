@@ -3,16 +3,15 @@ use std::{
     fs::{self, File},
     hash::{Hash, Hasher},
     io::{BufReader, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 
-use crate::{config::Config, Cli};
+use crate::{config::Config, theme::ThemeValue, Cli};
 
-pub const CACHE_FILE_PATH: &str = "lirstings.cache.json";
 pub const CACHE_SKIP_MESSAGE: &str = "lirstings: skipping generation of cached input";
 pub const CACHE_WRITE_MESSAGE: &str = "lirstings: written to cache";
 
@@ -20,11 +19,11 @@ pub const CACHE_WRITE_MESSAGE: &str = "lirstings: written to cache";
 pub struct Cache(HashMap<u64, String>);
 
 impl Cache {
-    pub fn set_entry(&mut self, hash: u64, output: String) -> Result<()> {
+    pub fn set_entry(&mut self, path: &Path, hash: u64, output: String) -> Result<()> {
         self.0.insert(hash, output);
 
         let repr = serde_json::to_vec(self).with_context(|| "could not marshal cache struct")?;
-        fs::write(CACHE_FILE_PATH, repr).with_context(|| "could not write to cache file")?;
+        fs::write(path, repr).with_context(|| "could not write to cache file")?;
 
         Ok(())
     }
@@ -35,18 +34,27 @@ impl Cache {
 }
 
 pub fn hash(cli: &Cli, code: &str, config: &Config, queries: Option<String>) -> u64 {
+    let mut config = config.clone();
+    // the same cache may be reached through different paths
+    config.cache_file = PathBuf::new();
+    if config.semantic_styles {
+        // the output only refers to theme keys, changing their styles must not invalidate it
+        config
+            .theme
+            .values_mut()
+            .for_each(|value| *value = ThemeValue::Color(String::new()));
+    }
     let mut hasher = DefaultHasher::new();
     (cli, code, config, queries).hash(&mut hasher);
     hasher.finish()
 }
 
-pub fn read() -> Result<Cache> {
-    // either read or create a configuration file based on it's current existence
-    let path = Path::new(CACHE_FILE_PATH);
+pub fn read(path: &Path) -> Result<Cache> {
+    // either read or create a cache file based on it's current existence
     match &path.exists() {
         true => {
             // the file exists, it can be read
-            let file = File::open(CACHE_FILE_PATH)?;
+            let file = File::open(path)?;
             let file_reader = BufReader::new(file);
             let cache: Cache = serde_json::from_reader(file_reader)?;
             Ok(cache)
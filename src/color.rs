@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::{
+    print,
+    theme::{Style, ThemeValue},
+};
+
+#[derive(ValueEnum, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub enum PrintMode {
+    /// Map colors to gray levels, adding bold and italic where levels run out
+    Grayscale,
+    /// Print everything in black, distinguishing colors by bold and italic
+    Bw,
+}
+
+/// Gray levels used in grayscale mode, all of which are readable on white paper
+const GRAY_LEVELS: &[&str] = &["#000000", "#404040", "#6a6a6a"];
+
+/// Minimum WCAG contrast ratio for normal text to pass level AA
+const MIN_CONTRAST: f64 = 4.5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+        Some(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+
+    /// Relative luminance as defined by WCAG 2
+    pub fn luminance(self) -> f64 {
+        let linear = |channel: u8| {
+            let channel = channel as f64 / 255.0;
+            match channel <= 0.03928 {
+                true => channel / 12.92,
+                false => ((channel + 0.055) / 1.055).powf(2.4),
+            }
+        };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+
+    pub fn contrast(self, other: Self) -> f64 {
+        let (lighter, darker) = match self.luminance() > other.luminance() {
+            true => (self.luminance(), other.luminance()),
+            false => (other.luminance(), self.luminance()),
+        };
+        (lighter + 0.05) / (darker + 0.05)
+    }
+
+    /// The gray with the same luminance
+    fn to_gray(self) -> Self {
+        let luminance = self.luminance();
+        let channel = match luminance <= 0.0031308 {
+            true => luminance * 12.92,
+            false => 1.055 * luminance.powf(1.0 / 2.4) - 0.055,
+        };
+        let channel = (channel * 255.0).round() as u8;
+        Self(channel, channel, channel)
+    }
+}
+
+/// Rewrites a resolved theme for printing without colors. Foreground and underline colors are
+/// sorted by luminance and split into as many buckets as there are gray levels (just one in `bw`
+/// mode). Within a bucket the most used color stays plain while the others get bold and italic
+/// variants, so that colors which were distinguishable before mostly stay distinguishable.
+pub fn apply_print_mode(theme: &mut BTreeMap<String, ThemeValue>, mode: PrintMode) {
+    let levels = match mode {
+        PrintMode::Grayscale => GRAY_LEVELS,
+        PrintMode::Bw => &GRAY_LEVELS[..1],
+    };
+
+    let mut usage: BTreeMap<String, usize> = BTreeMap::new();
+    for value in theme.values() {
+        let underline_color = match value {
+            ThemeValue::Object(style) => style.underline_color.as_deref(),
+            ThemeValue::Color(_) => None,
+        };
+        for color in foreground(value).into_iter().chain(underline_color) {
+            *usage.entry(color.to_string()).or_default() += 1;
+        }
+    }
+    let mut colors: Vec<_> = usage
+        .keys()
+        .filter_map(|color| Some((color.clone(), Rgb::from_hex(color)?.luminance())))
+        .collect();
+    colors.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    let mut buckets = vec![vec![]; levels.len()];
+    for (index, (color, _)) in colors.iter().enumerate() {
+        buckets[index * levels.len() / colors.len()].push(color.clone());
+    }
+    let mut mapping = BTreeMap::new();
+    for (level, mut bucket) in buckets.into_iter().enumerate() {
+        bucket.sort_by_key(|color| std::cmp::Reverse(usage[color]));
+        for (variant, color) in bucket.into_iter().enumerate() {
+            // the variants cycle through plain, bold, italic and bold italic
+            let bold = variant % 2 == 1;
+            let italic = variant % 4 >= 2;
+            mapping.insert(color, (levels[level], bold, italic));
+        }
+    }
+
+    for value in theme.values_mut() {
+        let mut style = match value {
+            ThemeValue::Color(color) => Style {
+                color: Some(color.clone()),
+                ..Style::default()
+            },
+            ThemeValue::Object(style) => style.clone(),
+        };
+        if let Some((gray, bold, italic)) = style.color.as_ref().and_then(|c| mapping.get(c)) {
+            style.color = Some(gray.to_string());
+            style.bold |= bold;
+            style.italic |= italic;
+        }
+        style.background = match mode {
+            PrintMode::Grayscale => style
+                .background
+                .as_deref()
+                .and_then(Rgb::from_hex)
+                .map(|background| background.to_gray().to_hex()),
+            PrintMode::Bw => None,
+        };
+        if let Some((gray, _, _)) = style.underline_color.as_ref().and_then(|c| mapping.get(c)) {
+            style.underline_color = Some(gray.to_string());
+        }
+        *value = ThemeValue::Object(style);
+    }
+}
+
+fn foreground(value: &ThemeValue) -> Option<&str> {
+    match value {
+        ThemeValue::Color(color) => Some(color),
+        ThemeValue::Object(style) => style.color.as_deref(),
+    }
+}
+
+/// Prints the WCAG contrast ratio of every theme entry against its background
+pub fn check(theme: &BTreeMap<String, ThemeValue>, background: &str) -> Result<()> {
+    let default_background = Rgb::from_hex(background)
+        .with_context(|| format!("invalid background color `{background}`"))?;
+    let mut failed = 0;
+    let mut out = String::new();
+    for (key, value) in theme {
+        let Some(color) = foreground(value) else {
+            continue;
+        };
+        let Some(rgb) = Rgb::from_hex(color) else {
+            out += &format!("   invalid    {key} ({color})\n");
+            failed += 1;
+            continue;
+        };
+        let background = match value {
+            ThemeValue::Object(Style {
+                background: Some(background),
+                ..
+            }) => Rgb::from_hex(background).unwrap_or(default_background),
+            _ => default_background,
+        };
+        let ratio = rgb.contrast(background);
+        let rating = if ratio >= 7.0 {
+            "AAA"
+        } else if ratio >= MIN_CONTRAST {
+            "AA"
+        } else if ratio >= 3.0 {
+            "AA large"
+        } else {
+            "fail"
+        };
+        if ratio < MIN_CONTRAST {
+            failed += 1;
+        }
+        out += &format!("{ratio:>5.2}:1  {rating:<8}  {key} ({color})\n");
+    }
+    out += &format!(
+        "{failed} entries have a contrast below {MIN_CONTRAST}:1 against {}\n",
+        default_background.to_hex()
+    );
+    print(&out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::{Style, UnderlineStyle};
+
+    fn theme() -> BTreeMap<String, ThemeValue> {
+        BTreeMap::from([
+            (
+                "keyword".to_string(),
+                ThemeValue::Color("#a626a4".to_string()),
+            ),
+            (
+                "error.underline".to_string(),
+                ThemeValue::Object(Style {
+                    underline_style: Some(UnderlineStyle::Wavy),
+                    underline_color: Some("#e45649".to_string()),
+                    ..Style::default()
+                }),
+            ),
+        ])
+    }
+
+    fn underline_color(theme: &BTreeMap<String, ThemeValue>) -> Option<&str> {
+        match &theme["error.underline"] {
+            ThemeValue::Object(style) => style.underline_color.as_deref(),
+            ThemeValue::Color(_) => None,
+        }
+    }
+
+    #[test]
+    fn grayscale_maps_underline_colors_to_gray_levels() {
+        let mut theme = theme();
+        apply_print_mode(&mut theme, PrintMode::Grayscale);
+        let color = underline_color(&theme).unwrap();
+        assert!(GRAY_LEVELS.contains(&color), "{color}");
+    }
+
+    #[test]
+    fn bw_maps_underline_colors_to_black() {
+        let mut theme = theme();
+        apply_print_mode(&mut theme, PrintMode::Bw);
+        assert_eq!(underline_color(&theme), Some("#000000"));
+    }
+}
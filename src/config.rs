@@ -1,85 +1,479 @@
 use std::{
     collections::BTreeMap,
+    env,
     fs::{self, File},
-    io::{BufReader, Write},
+    io::Write,
+    mem,
     path::{Path, PathBuf},
 };
 
-use crate::theme::ThemeValue;
-use anyhow::{Context, Result};
+use crate::{
+    range::ColumnUnit,
+    theme::{NamedTheme, ThemeValue},
+};
+use anyhow::{bail, Context, Result};
+use schemars::{schema::RootSchema, JsonSchema};
 use serde::Deserialize;
+use serde_json::Value;
 
 pub const CONFIG_FILE_PATH: &str = "lirstings.json";
+/// Config file names looked for in each directory, earlier ones take precedence. `.json` files
+/// are parsed as JSON5, so they may contain comments and trailing commas as well.
+pub const CONFIG_FILE_NAMES: &[&str] = &["lirstings.json", "lirstings.json5", "lirstings.toml"];
+pub const CACHE_FILE_NAME: &str = "lirstings.cache.json";
+
+/// Keys whose lists are concatenated instead of replaced when layering config files. Entries of
+/// later layers come last, so their query files take precedence.
+const CONCATENATED_KEYS: &[&str] = &["query_search_dirs", "parser_search_dirs"];
 
-#[derive(Deserialize, Clone, Hash, Debug)]
+/// Every key is optional in a config file, the embedded default config is always the first layer
+#[derive(Deserialize, JsonSchema, Clone, Hash, Debug)]
 pub struct Config {
+    /// Styles by capture name, values starting with `$` link to other keys
+    #[serde(default)]
     pub theme: BTreeMap<String, ThemeValue>,
+    /// Alternative themes selected with `--theme`
+    #[serde(default)]
+    pub themes: BTreeMap<String, NamedTheme>,
+    /// Additional capture renames as `old: new`
+    #[serde(default)]
+    pub capture_aliases: BTreeMap<String, String>,
+    /// Globs of directories containing a `queries/<language>` directory per language. These are
+    /// read as Neovim queries, whose later patterns take precedence, and override the queries
+    /// of bundled grammars.
+    #[serde(default)]
     pub query_search_dirs: Vec<String>,
+    /// Directories containing tree-sitter grammar repositories
+    #[serde(default)]
     pub parser_search_dirs: Vec<PathBuf>,
+    /// The 256 ANSI colors as `rrggbb`
+    #[serde(default)]
     pub ansi_colors: Vec<String>,
+    /// Comment syntax by file extension, used for elisions between ranges
+    #[serde(default)]
     pub comment_map: BTreeMap<String, CommentStyle>,
+    /// Refer to theme keys by name instead of inlining their styles
+    #[serde(default)]
+    pub semantic_styles: bool,
+    /// Color parameters, local variables and references to them with the theme keys
+    /// `parameter`, `parameter.reference`, `variable.local` and `variable.local.reference`
+    /// based on the locals query
+    #[serde(default)]
+    pub scope_highlights: bool,
+    /// Give each parameter and local variable its own color from the theme keys `rainbow.1`,
+    /// `rainbow.2` and so on
+    #[serde(default)]
+    pub rainbow_identifiers: bool,
+    /// Color bracket pairs by their nesting depth with the same theme keys
+    #[serde(default)]
+    pub rainbow_brackets: bool,
+    /// Mark code that could not be parsed with the `error.underline` theme style, layered on top
+    /// of the highlighting
+    #[serde(default)]
+    pub underline_errors: bool,
+    /// Templates wrapping inline code so that fragments like expressions are parsed correctly,
+    /// by file extension and context name. `{code}` is replaced by the code. A `default` context
+    /// is used unless one is selected with `--context`, without one the code is parsed as it is.
+    #[serde(default)]
+    pub inline_templates: BTreeMap<String, BTreeMap<String, String>>,
+    /// Whether the columns of ranges count `chars` or display `cells`
+    #[serde(default)]
+    pub column_unit: ColumnUnit,
+    /// Tabs are expanded to the next multiple of this many columns before ranges are selected
+    /// and indentation is gobbled, 0 keeps them and is the default
+    #[serde(default)]
+    pub tab_width: usize,
+    /// Defaults to `lirstings.cache.json` next to the project config
+    #[serde(default = "default_cache_file")]
+    pub cache_file: PathBuf,
 }
 
-#[derive(Deserialize, Clone, Hash, Debug)]
+fn default_cache_file() -> PathBuf {
+    PathBuf::from(CACHE_FILE_NAME)
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Hash, Debug)]
 pub struct CommentStyle {
     pub line: String,
     pub block: (String, String),
 }
 
-impl Config {
-    pub fn read() -> Result<Option<Config>> {
-        // either read or create a configuration file based on it's current existence
-        let path = Path::new(CONFIG_FILE_PATH);
-        match &path.exists() {
-            true => {
-                // the file exists, it can be read
-                let config_file = File::open(CONFIG_FILE_PATH)?;
-                let config_file_reader = BufReader::new(config_file);
-                let config: Config = serde_json::from_reader(config_file_reader)?;
-                Ok(Some(config))
+/// One of the files making up the config, later layers override earlier ones
+pub struct ConfigLayer {
+    /// `None` for the embedded default config
+    pub path: Option<PathBuf>,
+    pub value: Value,
+}
+
+impl ConfigLayer {
+    fn read(path: PathBuf) -> Result<Self> {
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("could not read `{}`", path.to_string_lossy()))?;
+        let value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&source).map_err(anyhow::Error::from),
+            _ => json5::from_str(&source).map_err(anyhow::Error::from),
+        };
+        let mut value: Value =
+            value.with_context(|| format!("could not parse `{}`", path.to_string_lossy()))?;
+        // relative paths are relative to the config file, absolute ones keep the cache hash
+        // independent of the directory lirstings is run from
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.canonicalize()?,
+            _ => env::current_dir()?,
+        };
+        resolve_relative_paths(&mut value, &dir);
+        Ok(Self {
+            path: Some(path),
+            value,
+        })
+    }
+
+    /// The embedded default config with its relative paths resolved against `dir`
+    fn embedded_default(dir: &Path) -> Self {
+        let mut value = serde_json::from_str(include_str!("default_config.json"))
+            .expect("the default config is valid JSON");
+        resolve_relative_paths(&mut value, dir);
+        Self { path: None, value }
+    }
+}
+
+/// Makes the search dirs and the cache file of a config layer absolute
+fn resolve_relative_paths(value: &mut Value, dir: &Path) {
+    if let Some(globs) = value
+        .get_mut("query_search_dirs")
+        .and_then(Value::as_array_mut)
+    {
+        for glob_str in globs.iter_mut() {
+            if let Some(relative) = glob_str.as_str().filter(|str| Path::new(str).is_relative()) {
+                let escaped_dir = glob::Pattern::escape(&dir.to_string_lossy());
+                *glob_str = Path::new(&escaped_dir)
+                    .join(relative)
+                    .to_string_lossy()
+                    .into();
             }
-            false => {
-                // The file does not exist, therefore create a new one
-                fs::create_dir_all(path.parent().unwrap())?;
-                let mut file = File::create(path)?;
-                file.write_all(include_bytes!("default_config.json"))?;
-                Ok(None)
+        }
+    }
+    for key in ["parser_search_dirs", "cache_file"] {
+        let paths = match value.get_mut(key) {
+            Some(Value::Array(paths)) => paths.iter_mut().collect(),
+            Some(path @ Value::String(_)) => vec![path],
+            _ => vec![],
+        };
+        for path in paths {
+            if let Some(relative) = path.as_str().filter(|str| Path::new(str).is_relative()) {
+                *path = dir.join(relative).to_string_lossy().into();
             }
         }
     }
+}
 
-    pub fn resolve_links(&mut self) -> Result<()> {
-        let mut must_reresolve = false;
-        let mut replacements = vec![];
-        for (key, value) in self.theme.iter() {
-            let link_key = match value {
-                ThemeValue::Color(str) if str.starts_with('$') => &str[1..],
-                ThemeValue::Object {
-                    link: Some(str), ..
-                } => str,
-                _ => continue,
-            };
-            let resolved = value.linked_to(
-                self.theme
-                    .get(link_key)
-                    .with_context(|| format!("link to unknown key `{link_key}`"))?,
-            );
-            if matches!(&resolved, ThemeValue::Color(str) if str.starts_with('$'))
-                || matches!(&resolved, ThemeValue::Object { link: Some(_), .. })
-            {
-                must_reresolve = true;
+/// The first `lirstings.json` in the current directory or one of its ancestors
+fn find_project_config() -> Result<Option<PathBuf>> {
+    let current_dir = env::current_dir()?;
+    Ok(current_dir.ancestors().find_map(config_file_in))
+}
+
+fn config_file_in(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// `path` if it is set, otherwise the project config found by searching upwards
+pub fn project_config_path(path: Option<&Path>) -> Result<Option<PathBuf>> {
+    match path {
+        Some(path) if !path.is_file() => {
+            bail!("config file `{}` does not exist", path.to_string_lossy())
+        }
+        Some(path) => Ok(Some(path.to_path_buf())),
+        None => find_project_config(),
+    }
+}
+
+/// `lirstings` in `$XDG_CONFIG_HOME`, or in `~/.config` if that is not set
+pub fn user_config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+    Some(config_home.join("lirstings"))
+}
+
+/// Collects the config layers: the embedded default, the user config and the project config,
+/// which is either `path` or found by searching upwards from the current directory. Relative
+/// paths of the default are resolved against the directory of the project config. If there is
+/// neither a user nor a project config and `use_default` is not set, `None` is returned.
+pub fn layers(path: Option<&Path>, use_default: bool) -> Result<Option<Vec<ConfigLayer>>> {
+    let project = project_config_path(path)?;
+    let user = user_config_dir().and_then(|dir| config_file_in(&dir));
+    if project.is_none() && user.is_none() && !use_default {
+        return Ok(None);
+    }
+
+    let default_dir = match project.as_ref().and_then(|project| project.parent()) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.canonicalize()?,
+        _ => env::current_dir()?,
+    };
+    let mut layers = vec![ConfigLayer::embedded_default(&default_dir)];
+    layers.extend(user.map(ConfigLayer::read).transpose()?);
+    if let Some(project) = project {
+        let mut layer = ConfigLayer::read(project.clone())?;
+        if layer.value.get("cache_file").is_none() {
+            let dir = project.parent().unwrap_or(Path::new("."));
+            layer.value["cache_file"] = dir.join(CACHE_FILE_NAME).to_string_lossy().into();
+        }
+        layers.push(layer);
+    }
+    Ok(Some(layers))
+}
+
+/// Merges the top-level keys of the layers. Objects like `theme` are merged entry by entry and
+/// the lists in `CONCATENATED_KEYS` are concatenated without duplicates, everything else is
+/// replaced.
+pub fn merge_layers(layers: &[ConfigLayer]) -> Value {
+    let mut merged = serde_json::Map::new();
+    for layer in layers {
+        let Value::Object(object) = &layer.value else {
+            continue;
+        };
+        for (key, value) in object {
+            match (merged.get_mut(key), value) {
+                (Some(Value::Object(base)), Value::Object(entries)) => {
+                    base.extend(entries.clone());
+                }
+                (Some(Value::Array(base)), Value::Array(entries))
+                    if CONCATENATED_KEYS.contains(&key.as_str()) =>
+                {
+                    // a later entry repeating an earlier one moves it to the end
+                    base.retain(|entry| !entries.contains(entry));
+                    base.extend(entries.clone());
+                }
+                _ => {
+                    merged.insert(key.clone(), value.clone());
+                }
             }
-            replacements.push((key.clone(), resolved));
         }
-        for (key, replacement) in replacements {
-            *self
-                .theme
-                .get_mut(&key)
-                .expect("key validity checked above") = replacement;
+    }
+    Value::Object(merged)
+}
+
+/// Names of the properties of an object schema
+pub fn property_names(schema: &RootSchema) -> Vec<String> {
+    schema
+        .schema
+        .object
+        .as_ref()
+        .map(|object| object.properties.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+impl Config {
+    /// Reads the config as described in `layers`. If there is no config file and `use_default`
+    /// is not set, a new `lirstings.json` is created in the current directory and `None` is
+    /// returned.
+    pub fn read(path: Option<&Path>, use_default: bool) -> Result<Option<Config>> {
+        let Some(layers) = layers(path, use_default)? else {
+            let mut file = File::create(CONFIG_FILE_PATH)?;
+            file.write_all(include_bytes!("default_config.json"))?;
+            return Ok(None);
+        };
+        let config: Config = serde_json::from_value(merge_layers(&layers))?;
+        config.validate_colors()?;
+        Ok(Some(config))
+    }
+
+    /// Rejects malformed colors in the theme and the named themes
+    fn validate_colors(&self) -> Result<()> {
+        let named = self.themes.iter().flat_map(|(name, theme)| {
+            theme
+                .entries
+                .iter()
+                .map(move |(key, value)| (format!("themes.{name}.{key}"), value))
+        });
+        let entries = self
+            .theme
+            .iter()
+            .map(|(key, value)| (format!("theme.{key}"), value))
+            .chain(named);
+        for (path, value) in entries {
+            value
+                .validate()
+                .with_context(|| format!("invalid `{path}`"))?;
         }
-        if must_reresolve {
-            self.resolve_links()?;
+        Ok(())
+    }
+
+    /// Keys of `Config` as listed by its schema, used by `doctor` to report unknown keys.
+    /// `$schema` is allowed as well so that config files can refer to the schema.
+    pub fn keys() -> Vec<String> {
+        let mut keys = property_names(&schemars::schema_for!(Config));
+        keys.push("$schema".to_string());
+        keys
+    }
+
+    /// A JSON Schema for config files
+    pub fn schema() -> String {
+        let schema = schemars::schema_for!(Config);
+        serde_json::to_string_pretty(&schema).expect("schemas can always be serialized")
+    }
+
+    /// Replaces `theme` with the named theme layered on top of it. The other named themes are
+    /// dropped so that they do not influence the cache hash.
+    pub fn select_theme(&mut self, name: Option<&str>) -> Result<()> {
+        let themes = mem::take(&mut self.themes);
+        if let Some(name) = name {
+            self.theme = Self::named_theme_entries(&self.theme, &themes, name, &mut vec![])?;
+        }
+        Ok(())
+    }
+
+    fn named_theme_entries(
+        base: &BTreeMap<String, ThemeValue>,
+        themes: &BTreeMap<String, NamedTheme>,
+        name: &str,
+        visited: &mut Vec<String>,
+    ) -> Result<BTreeMap<String, ThemeValue>> {
+        if visited.iter().any(|visited_name| visited_name == name) {
+            bail!(
+                "theme inheritance cycle: {} -> {name}",
+                visited.join(" -> ")
+            );
+        }
+        visited.push(name.to_string());
+        let theme = themes.get(name).with_context(|| {
+            format!(
+                "unknown theme `{name}`, available themes are: {}",
+                themes.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        let mut entries = match &theme.inherits {
+            Some(parent) => Self::named_theme_entries(base, themes, parent, visited)?,
+            None => base.clone(),
+        };
+        entries.extend(theme.entries.clone());
+        Ok(entries)
+    }
+
+    pub fn resolve_links(&mut self) -> Result<()> {
+        let mut resolved = BTreeMap::new();
+        for key in self.theme.keys() {
+            resolved.insert(key.clone(), self.resolved_value(key, &mut vec![])?);
         }
+        self.theme = resolved;
         Ok(())
     }
+
+    /// Returns the value of a theme key merged with everything it (transitively) links to
+    pub fn resolved_value(&self, key: &str, visited: &mut Vec<String>) -> Result<ThemeValue> {
+        if visited.iter().any(|visited_key| visited_key == key) {
+            bail!("link cycle: {} -> {key}", visited.join(" -> "));
+        }
+        let value = match visited.last() {
+            Some(linking_key) => self
+                .theme
+                .get(key)
+                .with_context(|| format!("link from `{linking_key}` to unknown key `{key}`"))?,
+            None => self
+                .theme
+                .get(key)
+                .with_context(|| format!("unknown theme key `{key}`"))?,
+        };
+        let Some(link_key) = value.link() else {
+            return Ok(value.clone());
+        };
+        visited.push(key.to_string());
+        Ok(value.linked_to(&self.resolved_value(link_key, visited)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Style;
+
+    fn layer(value: Value) -> ConfigLayer {
+        ConfigLayer { path: None, value }
+    }
+
+    #[test]
+    fn merges_layers() {
+        let merged = merge_layers(&[
+            layer(serde_json::json!({
+                "theme": {"keyword": "#000000", "string": "#111111"},
+                "query_search_dirs": ["a", "b"],
+                "tab_width": 4,
+            })),
+            layer(serde_json::json!({
+                "theme": {"string": "#222222"},
+                "query_search_dirs": ["a", "c"],
+                "tab_width": 8,
+            })),
+        ]);
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "theme": {"keyword": "#000000", "string": "#222222"},
+                "query_search_dirs": ["b", "a", "c"],
+                "tab_width": 8,
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_relative_paths() {
+        let mut value = serde_json::json!({
+            "query_search_dirs": ["queries/*", "/abs/queries"],
+            "parser_search_dirs": ["parsers", "/abs/parsers"],
+            "cache_file": "cache.json",
+        });
+        resolve_relative_paths(&mut value, Path::new("/project [1]"));
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "query_search_dirs": ["/project [[]1[]]/queries/*", "/abs/queries"],
+                "parser_search_dirs": ["/project [1]/parsers", "/abs/parsers"],
+                "cache_file": "/project [1]/cache.json",
+            })
+        );
+    }
+
+    #[test]
+    fn layers_start_with_the_default_resolved_against_the_project() {
+        let dir = env::temp_dir().join(format!("lirstings-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let path = dir.join(CONFIG_FILE_PATH);
+        fs::write(&path, r#"{"parser_search_dirs": ["parsers"]}"#).unwrap();
+        let layers = layers(Some(&path), false).unwrap().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(layers[0].path.is_none());
+        assert_eq!(
+            layers[0].value["parser_search_dirs"],
+            serde_json::json!([dir.join("./tree-sitter/").to_string_lossy()])
+        );
+        let project = layers.last().unwrap();
+        assert_eq!(project.path.as_deref(), Some(path.as_path()));
+        assert_eq!(
+            project.value["parser_search_dirs"],
+            serde_json::json!([dir.join("parsers").to_string_lossy()])
+        );
+        assert_eq!(
+            project.value["cache_file"],
+            serde_json::json!(dir.join(CACHE_FILE_NAME).to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn keys_come_from_the_schema() {
+        let keys = Config::keys();
+        let default: Value = json5::from_str(include_str!("default_config.json")).unwrap();
+        for key in default.as_object().unwrap().keys() {
+            assert!(keys.contains(key), "{key}");
+        }
+        assert!(keys.contains(&"$schema".to_string()));
+        assert!(Style::keys().contains(&"underline_color".to_string()));
+        assert!(!Style::keys().contains(&"theme".to_string()));
+    }
 }
@@ -0,0 +1,317 @@
+use std::{collections::BTreeSet, fs};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tree_sitter::{Language, Query};
+
+use crate::{
+    bundled,
+    color::Rgb,
+    config::{self, Config, CONFIG_FILE_NAMES},
+    print, query,
+    theme::Style,
+    ts, Cli,
+};
+
+/// Collects problems while checking instead of stopping at the first one
+#[derive(Default)]
+struct Report {
+    out: String,
+    problems: usize,
+}
+
+impl Report {
+    fn line(&mut self, line: &str) {
+        self.out += line;
+        self.out.push('\n');
+    }
+
+    fn problem(&mut self, problem: &str) {
+        self.problems += 1;
+        self.line(&format!("  error: {problem}"));
+    }
+}
+
+pub fn run(cli: &Cli) -> Result<()> {
+    let mut report = Report::default();
+    let config = check_config(cli, &mut report);
+    if let Some(config) = &config {
+        report.line("grammars");
+        if let Err(err) = check_grammars(config, &mut report) {
+            report.problem(&format!("{err:#}"));
+        }
+    }
+    print(&report.out);
+    match report.problems {
+        0 => Ok(()),
+        1 => bail!("found 1 problem"),
+        problems => bail!("found {problems} problems"),
+    }
+}
+
+/// Checks each config file for problems which deserialization either misses or reports one at a
+/// time, then returns the merged config if possible
+fn check_config(cli: &Cli, report: &mut Report) -> Option<Config> {
+    let layers = match config::layers(cli.config.as_deref(), cli.default_config) {
+        Ok(Some(layers)) => layers,
+        Ok(None) => {
+            report.line("config");
+            report.problem(&format!(
+                "no config file found in the current directory, its ancestors or {}, looked for {}",
+                config::user_config_dir().map_or("`~/.config/lirstings`".to_string(), |dir| {
+                    format!("`{}`", dir.to_string_lossy())
+                }),
+                CONFIG_FILE_NAMES.join(", ")
+            ));
+            return None;
+        }
+        Err(err) => {
+            report.line("config");
+            report.problem(&format!("{err:#}"));
+            return None;
+        }
+    };
+    for layer in &layers {
+        match &layer.path {
+            Some(path) => report.line(&format!("config `{}`", path.to_string_lossy())),
+            None => report.line("config (embedded default)"),
+        }
+        let problems = report.problems;
+        check_config_value(&layer.value, report);
+        if report.problems == problems {
+            report.line("  ok");
+        }
+    }
+
+    report.line("merged config");
+    let problems = report.problems;
+    let config: Config = match serde_json::from_value(config::merge_layers(&layers)) {
+        Ok(config) => config,
+        Err(err) => {
+            report.problem(&format!("invalid config: {err}"));
+            return None;
+        }
+    };
+    check_links(&config, report);
+    if report.problems == problems {
+        report.line("  ok");
+    }
+    Some(config)
+}
+
+fn check_config_value(value: &Value, report: &mut Report) {
+    let Some(object) = value.as_object() else {
+        report.problem("the config must be a JSON object");
+        return;
+    };
+    let known_keys = Config::keys();
+    for key in object.keys() {
+        if !known_keys.contains(key) {
+            report.problem(&format!("unknown key `{key}`"));
+        }
+    }
+    if let Some(theme) = value["theme"].as_object() {
+        for (key, value) in theme {
+            check_theme_value(&format!("theme.{key}"), value, report);
+        }
+    }
+    if let Some(themes) = value["themes"].as_object() {
+        for (name, theme) in themes.iter().filter_map(|(n, t)| Some((n, t.as_object()?))) {
+            for (key, value) in theme.iter().filter(|(key, _)| *key != "inherits") {
+                check_theme_value(&format!("themes.{name}.{key}"), value, report);
+            }
+        }
+    }
+    if let Some(templates) = value["inline_templates"].as_object() {
+        for (file_ext, contexts) in templates
+            .iter()
+            .filter_map(|(e, c)| Some((e, c.as_object()?)))
+        {
+            for (context, template) in contexts {
+                if template
+                    .as_str()
+                    .is_some_and(|template| !template.contains(ts::TEMPLATE_PLACEHOLDER))
+                {
+                    report.problem(&format!(
+                        "inline_templates.{file_ext}.{context}: template does not contain `{}`",
+                        ts::TEMPLATE_PLACEHOLDER
+                    ));
+                }
+            }
+        }
+    }
+    if let Some(colors) = value["ansi_colors"].as_array() {
+        for color in colors.iter().filter_map(Value::as_str) {
+            if Rgb::from_hex(&format!("#{color}")).is_none() {
+                report.problem(&format!(
+                    "ansi_colors: malformed color `{color}`, expected `rrggbb`"
+                ));
+            }
+        }
+    }
+}
+
+fn check_theme_value(path: &str, value: &Value, report: &mut Report) {
+    let check_color = |field: &str, report: &mut Report| {
+        let Some(color) = value[field].as_str() else {
+            return;
+        };
+        if Rgb::from_hex(color).is_none() {
+            report.problem(&format!(
+                "{path}.{field}: malformed color `{color}`, expected `#rrggbb`"
+            ));
+        }
+    };
+    match value {
+        Value::String(color) if color.starts_with('$') => {}
+        Value::String(color) if Rgb::from_hex(color).is_none() => report.problem(&format!(
+            "{path}: malformed color `{color}`, expected `#rrggbb` or `$key`"
+        )),
+        Value::Object(style) => {
+            let known_keys = Style::keys();
+            for key in style.keys() {
+                if !known_keys.contains(key) {
+                    report.problem(&format!("{path}: unknown style key `{key}`"));
+                }
+            }
+            for field in ["color", "background", "underline_color"] {
+                check_color(field, report);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Resolves the links of the base theme and of every named theme, reporting problems of named
+/// themes only if the base theme does not have the same problem
+fn check_links(config: &Config, report: &mut Report) {
+    let mut seen = BTreeSet::new();
+    let names = std::iter::once(None).chain(config.themes.keys().map(|name| Some(name.as_str())));
+    for name in names {
+        let mut selected = config.clone();
+        if let Err(err) = selected.select_theme(name) {
+            report.problem(&format!("themes.{}: {err:#}", name.unwrap_or_default()));
+            continue;
+        }
+        for key in selected.theme.keys() {
+            let Err(err) = selected.resolved_value(key, &mut vec![]) else {
+                continue;
+            };
+            let message = format!("{err:#}");
+            if seen.insert(message.clone()) {
+                match name {
+                    Some(name) => report.problem(&format!("themes.{name}: {message}")),
+                    None => report.problem(&format!("theme: {message}")),
+                }
+            }
+        }
+    }
+}
+
+/// Lists every bundled grammar and every grammar found in the parser search dirs together with
+/// their query files and checks that the queries compile against them
+fn check_grammars(config: &Config, report: &mut Report) -> Result<()> {
+    for grammar in bundled::GRAMMARS {
+        report.line(&format!(
+            "  {} ({}) bundled",
+            grammar.name,
+            file_types(grammar.file_types)
+        ));
+        check_queries(
+            config,
+            report,
+            (grammar.language)(),
+            grammar.name,
+            grammar.queries(),
+        )?;
+    }
+
+    let loader = ts::loader(config)?;
+    let lang_configs = loader.get_all_language_configurations();
+    if lang_configs.is_empty() && bundled::GRAMMARS.is_empty() {
+        report.problem(&format!(
+            "no grammars found in parser search dirs {:?}",
+            config.parser_search_dirs
+        ));
+    }
+    for (lang_config, path) in lang_configs {
+        let parser_name = match ts::parser_name(lang_config) {
+            Ok(name) => name,
+            Err(err) => {
+                report.problem(&format!("{}: {err:#}", path.to_string_lossy()));
+                continue;
+            }
+        };
+        report.line(&format!(
+            "  {parser_name} ({}) at `{}`",
+            file_types(&lang_config.file_types),
+            path.to_string_lossy()
+        ));
+        let scope = lang_config
+            .scope
+            .as_deref()
+            .expect("checked by parser_name");
+        let lang = match loader.language_configuration_for_scope(scope) {
+            Ok(Some((lang, _))) => lang,
+            Ok(None) => unreachable!("the configuration was found by scope"),
+            Err(err) => {
+                report.problem(&format!("could not load grammar: {err:#}"));
+                continue;
+            }
+        };
+        check_queries(config, report, lang, &parser_name, Default::default())?;
+    }
+    Ok(())
+}
+
+fn file_types(file_types: &[impl AsRef<str>]) -> String {
+    file_types
+        .iter()
+        .map(|file_type| format!(".{}", file_type.as_ref()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Checks the query files found for a grammar, falling back to the embedded queries of bundled
+/// grammars
+fn check_queries(
+    config: &Config,
+    report: &mut Report,
+    lang: Language,
+    parser_name: &str,
+    embedded_queries: [String; 3],
+) -> Result<()> {
+    let query_files = ts::query_files(config, parser_name)?;
+    let has_embedded_highlights = !embedded_queries[0].is_empty();
+    for ((file, embedded), name) in query_files
+        .iter()
+        .zip(embedded_queries)
+        .zip(ts::QUERY_FILE_NAMES)
+    {
+        let (source, origin) = match file {
+            Some(file) => (
+                fs::read_to_string(file)
+                    .with_context(|| format!("could not read `{}`", file.to_string_lossy()))
+                    .and_then(|source| query::process(&source)),
+                format!("`{}`", file.to_string_lossy()),
+            ),
+            None if !embedded.is_empty() => (Ok(embedded), "embedded".to_string()),
+            None => {
+                report.line(&format!("    {name}: not found"));
+                continue;
+            }
+        };
+        let result = source.and_then(|source| Ok(Query::new(lang, &source)?));
+        match result {
+            Ok(_) => report.line(&format!("    {name}: {origin} ok")),
+            Err(err) => report.problem(&format!("{parser_name} {name} ({origin}): {err:#}")),
+        }
+    }
+    if query_files[0].is_none() && !has_embedded_highlights {
+        report.problem(&format!(
+            "no highlights.scm for `{parser_name}` in query search dirs {:?}",
+            config.query_search_dirs
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::{cmp::Ordering, ops::RangeInclusive, str::FromStr};
+
+use anyhow::{bail, Context};
+use tree_sitter::{Node, Tree};
+
+use crate::range::{Offset, Range};
+
+/// A node type whose nodes have their inner lines replaced by a placeholder, like `block` for
+/// function bodies. With `>n` only nodes nested in more than `n` nodes of the same type are
+/// elided, so `match_arm>1` keeps the arms of outer `match` expressions.
+#[derive(Debug, Clone, Hash)]
+pub struct ElideSpec {
+    pub node_type: String,
+    pub min_depth: usize,
+}
+
+impl FromStr for ElideSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (node_type, min_depth) = match s.split_once('>') {
+            Some((node_type, depth)) => (
+                node_type.trim(),
+                depth
+                    .trim()
+                    .parse()
+                    .with_context(|| "failed to parse elision depth")?,
+            ),
+            None => (s.trim(), 0),
+        };
+        if node_type.is_empty() {
+            bail!("missing node type to elide");
+        }
+        Ok(Self {
+            node_type: node_type.to_string(),
+            min_depth,
+        })
+    }
+}
+
+/// The rows between the first and the last line of every node matching one of `specs`, sorted
+/// and without overlaps. Nodes spanning less than three lines have no inner lines to elide.
+pub fn hidden_rows(tree: &Tree, specs: &[ElideSpec]) -> Vec<RangeInclusive<usize>> {
+    let mut rows = vec![];
+    visit(tree.root_node(), specs, &mut vec![], &mut rows);
+    rows
+}
+
+fn visit<'tree>(
+    node: Node<'tree>,
+    specs: &[ElideSpec],
+    ancestors: &mut Vec<&'tree str>,
+    rows: &mut Vec<RangeInclusive<usize>>,
+) {
+    let depth = ancestors
+        .iter()
+        .filter(|kind| **kind == node.kind())
+        .count()
+        + 1;
+    let start_row = node.start_position().row;
+    let end_row = end_row(node);
+    if end_row > start_row + 1
+        && specs
+            .iter()
+            .any(|spec| spec.node_type == node.kind() && depth > spec.min_depth)
+    {
+        rows.push(start_row + 1..=end_row - 1);
+        return;
+    }
+    ancestors.push(node.kind());
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        visit(child, specs, ancestors, rows);
+    }
+    ancestors.pop();
+}
+
+/// The last row of a node, which is the row before its end for nodes including their trailing
+/// newline
+pub fn end_row(node: Node) -> usize {
+    match node.end_position() {
+        end if end.column == 0 && end.row > node.start_position().row => end.row - 1,
+        end => end.row,
+    }
+}
+
+/// Splits `ranges` so that they skip the `hidden` rows, except for the last row of each range.
+/// Pieces following hidden rows are indented so that the placeholder written between the pieces
+/// lines up with the first hidden line.
+pub fn split_ranges(
+    ranges: &[Range],
+    hidden: &[RangeInclusive<usize>],
+    lines: &[&str],
+) -> Vec<Range> {
+    let indent = |row: usize| {
+        lines.get(row).map_or(0, |line| {
+            line.chars().take_while(|char| *char == ' ').count()
+        })
+    };
+    let mut pieces: Vec<Range> = vec![];
+    for range in ranges {
+        let mut start = range.start;
+        let mut placeholder_indent = None;
+        for rows in hidden
+            .iter()
+            .filter(|rows| *rows.end() >= range.start && *rows.start() <= range.end)
+        {
+            // placeholders are only written between pieces, so the last row is always kept
+            let Some(end) = range
+                .end
+                .checked_sub(1)
+                .map(|last| last.min(*rows.end()))
+                .filter(|end| end >= rows.start())
+            else {
+                continue;
+            };
+            if *rows.start() > start {
+                let previous_end = pieces.last().map(|piece| piece.end);
+                pieces.push(piece(
+                    range,
+                    start..=rows.start() - 1,
+                    previous_end,
+                    placeholder_indent,
+                    &indent,
+                ));
+            }
+            start = start.max(end + 1);
+            placeholder_indent = Some(indent(*rows.start()));
+        }
+        if start <= range.end {
+            let previous_end = pieces.last().map(|piece| piece.end);
+            pieces.push(piece(
+                range,
+                start..=range.end,
+                previous_end,
+                placeholder_indent,
+                &indent,
+            ));
+        }
+    }
+    pieces
+}
+
+/// A part of `range`, keeping its columns and its own indent offset only where it starts or ends
+/// the same way
+fn piece(
+    range: &Range,
+    rows: RangeInclusive<usize>,
+    previous_end: Option<usize>,
+    placeholder_indent: Option<usize>,
+    indent: &dyn Fn(usize) -> usize,
+) -> Range {
+    let (start, end) = rows.into_inner();
+    let is_first = start == range.start;
+    let indent_offset = match (is_first, placeholder_indent) {
+        (true, _) | (_, None) => range.indent_offset,
+        (false, Some(placeholder_indent)) => {
+            // the range code indents placeholders like the larger of the surrounding lines
+            let surrounding = indent(start).max(previous_end.map_or(0, indent));
+            match placeholder_indent.cmp(&surrounding) {
+                Ordering::Greater => Offset::Positive(placeholder_indent - surrounding),
+                Ordering::Less => Offset::Negative(surrounding - placeholder_indent),
+                Ordering::Equal => Offset::None,
+            }
+        }
+    };
+    Range {
+        inline: is_first && range.inline,
+        indent_offset,
+        start,
+        end,
+        start_col: range.start_col.filter(|_| is_first),
+        end_col: range.end_col.filter(|_| end == range.end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: &[&str] = &[
+        "fn main() {",
+        "    let a = 1;",
+        "    let b = 2;",
+        "    a + b",
+        "}",
+        "",
+        "fn foo() {}",
+    ];
+
+    fn range(start: usize, end: usize) -> Range {
+        Range {
+            start,
+            end,
+            ..Range::default()
+        }
+    }
+
+    fn rows(pieces: &[Range]) -> Vec<(usize, usize)> {
+        pieces
+            .iter()
+            .map(|piece| (piece.start, piece.end))
+            .collect()
+    }
+
+    #[test]
+    fn parses_node_types_and_depths() {
+        let spec: ElideSpec = "block".parse().unwrap();
+        assert_eq!((spec.node_type.as_str(), spec.min_depth), ("block", 0));
+        let spec: ElideSpec = " match_arm > 1 ".parse().unwrap();
+        assert_eq!((spec.node_type.as_str(), spec.min_depth), ("match_arm", 1));
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        assert!("".parse::<ElideSpec>().is_err());
+        assert!(">1".parse::<ElideSpec>().is_err());
+        assert!("block>".parse::<ElideSpec>().is_err());
+        assert!("block>-1".parse::<ElideSpec>().is_err());
+    }
+
+    #[test]
+    fn splits_ranges_around_hidden_rows() {
+        let pieces = split_ranges(&[range(0, 6)], &[1..=3], LINES);
+        assert_eq!(rows(&pieces), [(0, 0), (4, 6)]);
+        // the placeholder is indented like the first hidden line
+        assert!(matches!(pieces[1].indent_offset, Offset::Positive(4)));
+    }
+
+    #[test]
+    fn keeps_the_last_row_of_a_range() {
+        let pieces = split_ranges(&[range(0, 3)], &[1..=3], LINES);
+        assert_eq!(rows(&pieces), [(0, 0), (3, 3)]);
+    }
+
+    #[test]
+    fn ignores_hidden_rows_outside_of_ranges() {
+        let pieces = split_ranges(&[range(5, 6)], &[1..=3], LINES);
+        assert_eq!(rows(&pieces), [(5, 6)]);
+    }
+
+    #[test]
+    fn keeps_columns_only_at_the_ends() {
+        let range = Range {
+            inline: true,
+            start_col: Some(3),
+            end_col: Some(5),
+            ..range(0, 4)
+        };
+        let pieces = split_ranges(&[range], &[1..=3], LINES);
+        assert_eq!(rows(&pieces), [(0, 0), (4, 4)]);
+        assert_eq!((pieces[0].start_col, pieces[0].end_col), (Some(3), None));
+        assert_eq!((pieces[1].start_col, pieces[1].end_col), (None, Some(5)));
+        assert!(pieces[0].inline && !pieces[1].inline);
+    }
+}
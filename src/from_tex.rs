@@ -7,9 +7,9 @@ use std::{
 
 use anyhow::Result;
 
-use crate::{print, range::Range, Cli, Command};
+use crate::{elide::ElideSpec, print, range::Ranges, Cli, Command};
 
-pub fn run(file: &Path, raw_args: &str) -> Result<()> {
+pub fn run(file: &Path, raw_args: &str, outer_cli: &Cli) -> Result<()> {
     // parse arguments to HashMap
     let mut args = HashMap::new();
     let mut temp_key = String::new();
@@ -61,24 +61,44 @@ pub fn run(file: &Path, raw_args: &str) -> Result<()> {
     // construct Cli struct
     let cli = Cli {
         fancyvrb_args: args.remove("fancyvrb").unwrap_or_default(),
-        subcommand: if args.get("ansi").map_or(false, |val| val == "true") {
+        theme: args.remove("theme").or_else(|| outer_cli.theme.clone()),
+        print_mode: outer_cli.print_mode,
+        config: outer_cli.config.clone(),
+        default_config: outer_cli.default_config,
+        strict: outer_cli.strict,
+        subcommand: if args.get("ansi").is_some_and(|val| val == "true") {
             Command::Ansi {
                 file: file.to_path_buf(),
             }
+        } else if args.get("outline").is_some_and(|val| val == "true") {
+            Command::Outline {
+                file: file.to_path_buf(),
+                filename_strip_prefix: args.remove("path prefix").map(PathBuf::from),
+                rev: args.remove("rev"),
+                show_rev: args.get("show rev").is_some_and(|val| val == "true"),
+            }
         } else {
             Command::TreeSitter {
                 file: file.to_path_buf(),
-                raw: args.get("raw").map_or(false, |val| val == "true"),
-                raw_queries: args.get("raw queries").map_or(false, |val| val == "true"),
-                ranges: args.get("ranges").map_or(Ok(vec![]), |val| {
-                    val.split(',').map(Range::from_str).collect()
+                raw: args.get("raw").is_some_and(|val| val == "true"),
+                raw_queries: args.get("raw queries").is_some_and(|val| val == "true"),
+                ranges: args
+                    .get("ranges")
+                    .map(|val| val.parse::<Ranges>())
+                    .transpose()?
+                    .into_iter()
+                    .collect(),
+                elide: args.get("elide").map_or(Ok(vec![]), |val| {
+                    val.split(',').map(ElideSpec::from_str).collect()
                 })?,
                 filename_strip_prefix: args.remove("path prefix").map(PathBuf::from),
+                rev: args.remove("rev"),
+                show_rev: args.get("show rev").is_some_and(|val| val == "true"),
             }
         },
     };
 
-    let continued = args.get("continued").map_or(false, |val| val == "true");
+    let continued = args.get("continued").is_some_and(|val| val == "true");
     let vspace = args.get("vspace").map_or("-1\\baselineskip", |val| val);
 
     // begin float or wrapfloat if set
@@ -87,10 +107,7 @@ pub fn run(file: &Path, raw_args: &str) -> Result<()> {
             "\\begin{{listing}}[{float}]{}\n",
             if continued {
                 "\\ContinuedFloat"
-            } else if args
-                .get("start continued")
-                .map_or(false, |val| val == "true")
-            {
+            } else if args.get("start continued").is_some_and(|val| val == "true") {
                 "\\ContinuedFloat*"
             } else {
                 ""
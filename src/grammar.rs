@@ -0,0 +1,311 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::{self, Config},
+    print, ts, Cli, GrammarCommand,
+};
+
+pub const LOCK_FILE_NAME: &str = "lirstings.lock.json";
+
+/// Grammar versions recorded by `grammar add` and `grammar build`, stored next to the project
+/// config so that it can be committed together with it
+#[derive(Serialize, Deserialize, Default)]
+struct LockFile {
+    grammars: BTreeMap<String, LockedGrammar>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+struct LockedGrammar {
+    /// Relative to the directory of the lock file if the grammar is inside of it
+    path: PathBuf,
+    /// The commit checked out in the grammar repository with a `-dirty` suffix if it has local
+    /// changes, or `None` if it is not a git repository
+    revision: Option<String>,
+    abi_version: usize,
+}
+
+pub fn run(config: &Config, cli: &Cli, subcommand: &GrammarCommand) -> Result<()> {
+    let project_config = config::project_config_path(cli.config.as_deref())?.with_context(|| {
+        "grammars are managed in the project config, but there is none, run lirstings once to create it"
+    })?;
+    let project_dir = project_config
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize()?;
+    let lock_path = project_dir.join(LOCK_FILE_NAME);
+    let mut lock_file = read_lock_file(&lock_path)?;
+
+    match subcommand {
+        GrammarCommand::Add { path } => {
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("`{}` does not exist", path.to_string_lossy()))?;
+            if !ts::is_grammar_dir(&path) {
+                bail!(
+                    "`{}` is not a grammar repository, it contains no `package.json`",
+                    path.to_string_lossy()
+                );
+            }
+            // the config is only changed once the grammar could be built
+            let mut config = config.clone();
+            config.parser_search_dirs.push(path.clone());
+            let built = build(&config, &project_dir, &mut lock_file, |root| root == path)?;
+            if built.is_empty() {
+                bail!("no grammar found in `{}`", path.to_string_lossy());
+            }
+            let entry = relative_to(&path, &project_dir);
+            edit_parser_search_dirs(&project_config, |dirs| {
+                if !dirs.contains(&entry) {
+                    dirs.push(entry.clone());
+                }
+            })?;
+            eprintln!(
+                "lirstings: added `{entry}` to `parser_search_dirs` in `{}`",
+                project_config.to_string_lossy()
+            );
+        }
+        GrammarCommand::List => {
+            let loader = ts::loader(config)?;
+            let mut out = String::new();
+            for (lang_config, root) in loader.get_all_language_configurations() {
+                let name = ts::parser_name(lang_config)?;
+                let revision = revision(root);
+                let status = match lock_file.grammars.get(&name) {
+                    None => "not locked".to_string(),
+                    Some(locked) if locked.revision == revision => "locked".to_string(),
+                    Some(locked) => format!(
+                        "locked at {}",
+                        locked.revision.as_deref().unwrap_or("unknown revision")
+                    ),
+                };
+                out += &format!(
+                    "{name}\t{}\t{}\t{status}\n",
+                    revision.as_deref().unwrap_or("-"),
+                    root.to_string_lossy()
+                );
+            }
+            print(&out);
+            return Ok(());
+        }
+        GrammarCommand::Build { names, locked } => {
+            let loader = ts::loader(config)?;
+            let roots: Vec<_> = loader
+                .get_all_language_configurations()
+                .into_iter()
+                .filter(|(lang_config, _)| {
+                    names.is_empty()
+                        || ts::parser_name(lang_config).is_ok_and(|name| names.contains(&name))
+                })
+                .map(|(_, root)| root.to_path_buf())
+                .collect();
+            // with `--locked`, locked grammars which were not found are reported below
+            if roots.is_empty() && !*locked {
+                bail!("no matching grammars found");
+            }
+            let previous = lock_file.grammars.clone();
+            let built = build(config, &project_dir, &mut lock_file, |root| {
+                roots.iter().any(|r| r == root)
+            })?;
+            if *locked {
+                let mut problems = vec![];
+                for name in &built {
+                    let grammar = &lock_file.grammars[name];
+                    match previous.get(name) {
+                        None => problems.push(format!("grammar `{name}` is not locked")),
+                        Some(locked) if locked != grammar => problems.push(format!(
+                            "grammar `{name}` differs, found revision {} with ABI version {}",
+                            grammar.revision.as_deref().unwrap_or("unknown"),
+                            grammar.abi_version
+                        )),
+                        Some(_) => {}
+                    }
+                }
+                for (name, locked) in &previous {
+                    if !built.contains(name) && (names.is_empty() || names.contains(name)) {
+                        problems.push(format!(
+                            "locked grammar `{name}` at `{}` was not found",
+                            locked.path.to_string_lossy()
+                        ));
+                    }
+                }
+                if !problems.is_empty() {
+                    bail!(
+                        "the grammars do not match `{LOCK_FILE_NAME}`:\n  {}",
+                        problems.join("\n  ")
+                    );
+                }
+                return Ok(());
+            }
+        }
+        GrammarCommand::Remove { name } => {
+            let loader = ts::loader(config)?;
+            let root = loader
+                .get_all_language_configurations()
+                .into_iter()
+                .find(|(lang_config, _)| ts::parser_name(lang_config).is_ok_and(|n| &n == name))
+                .map(|(_, root)| root.to_path_buf())
+                .with_context(|| format!("no grammar named `{name}` found"))?;
+            let entry = relative_to(&root, &project_dir);
+            let mut removed = false;
+            edit_parser_search_dirs(&project_config, |dirs| {
+                let len = dirs.len();
+                dirs.retain(|dir| {
+                    dir != &entry
+                        && project_dir
+                            .join(dir)
+                            .canonicalize()
+                            .map_or(true, |dir| dir != root)
+                });
+                removed = dirs.len() != len;
+            })?;
+            if !removed {
+                bail!(
+                    "`{}` is not listed in the `parser_search_dirs` of `{}`, it is probably found in one of the listed directories",
+                    root.to_string_lossy(),
+                    project_config.to_string_lossy()
+                );
+            }
+            lock_file
+                .grammars
+                .retain(|_, grammar| project_dir.join(&grammar.path) != root);
+            eprintln!(
+                "lirstings: removed `{entry}` from `parser_search_dirs` in `{}`",
+                project_config.to_string_lossy()
+            );
+        }
+    }
+
+    let repr = serde_json::to_string_pretty(&lock_file)? + "\n";
+    fs::write(&lock_path, repr)
+        .with_context(|| format!("could not write `{}`", lock_path.to_string_lossy()))?;
+    Ok(())
+}
+
+fn read_lock_file(path: &Path) -> Result<LockFile> {
+    if !path.exists() {
+        return Ok(LockFile::default());
+    }
+    let source = fs::read_to_string(path)?;
+    serde_json::from_str(&source)
+        .with_context(|| format!("could not parse `{}`", path.to_string_lossy()))
+}
+
+/// Compiles every grammar whose root matches `filter` and records it in the lock file, returns
+/// the names of the built grammars
+fn build(
+    config: &Config,
+    project_dir: &Path,
+    lock_file: &mut LockFile,
+    filter: impl Fn(&Path) -> bool,
+) -> Result<Vec<String>> {
+    let loader = ts::loader(config)?;
+    let mut built = vec![];
+    for (lang_config, root) in loader.get_all_language_configurations() {
+        if !filter(root) {
+            continue;
+        }
+        let name = ts::parser_name(lang_config)?;
+        let scope = lang_config
+            .scope
+            .as_deref()
+            .expect("checked by parser_name");
+        let (lang, _) = loader
+            .language_configuration_for_scope(scope)
+            .with_context(|| format!("could not build grammar `{name}`"))?
+            .expect("the configuration was found by scope");
+        let grammar = LockedGrammar {
+            path: PathBuf::from(relative_to(root, project_dir)),
+            revision: revision(root),
+            abi_version: lang.version(),
+        };
+        eprintln!(
+            "lirstings: built `{name}` at revision {} with ABI version {}",
+            grammar.revision.as_deref().unwrap_or("unknown"),
+            grammar.abi_version
+        );
+        lock_file.grammars.insert(name.clone(), grammar);
+        built.push(name);
+    }
+    Ok(built)
+}
+
+fn revision(root: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = process::Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .output()
+            .ok()?;
+        match output.status.success() {
+            true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            false => None,
+        }
+    };
+    let commit = git(&["rev-parse", "HEAD"])?;
+    match git(&["status", "--porcelain", "--untracked-files=no", "."]) {
+        Some(changes) if changes.is_empty() => Some(commit),
+        _ => Some(commit + "-dirty"),
+    }
+}
+
+/// `path` relative to `dir` if it is inside of it, as written to config and lock files
+fn relative_to(path: &Path, dir: &Path) -> String {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    match canonical.strip_prefix(dir) {
+        Ok(relative) => format!("./{}", relative.to_string_lossy()),
+        Err(_) => canonical.to_string_lossy().to_string(),
+    }
+}
+
+/// Changes `parser_search_dirs` in a config file. TOML files keep their formatting, JSON files
+/// are rewritten and JSON files using JSON5 syntax have to be edited manually.
+fn edit_parser_search_dirs(path: &Path, edit: impl FnOnce(&mut Vec<String>)) -> Result<()> {
+    let source = fs::read_to_string(path)?;
+    let output = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => {
+            let mut document: toml_edit::Document = source.parse()?;
+            let mut dirs = document
+                .get("parser_search_dirs")
+                .and_then(|dirs| dirs.as_array())
+                .map(|dirs| dirs.iter().filter_map(|dir| dir.as_str()))
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect();
+            edit(&mut dirs);
+            document["parser_search_dirs"] =
+                toml_edit::value(dirs.into_iter().collect::<toml_edit::Array>());
+            document.to_string()
+        }
+        _ => {
+            let mut value: Value = serde_json::from_str(&source).with_context(|| {
+                format!(
+                    "`{}` is not plain JSON, please edit `parser_search_dirs` manually",
+                    path.to_string_lossy()
+                )
+            })?;
+            let mut dirs = value["parser_search_dirs"]
+                .as_array()
+                .map(|dirs| dirs.iter().filter_map(Value::as_str))
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect();
+            edit(&mut dirs);
+            value["parser_search_dirs"] = dirs.into();
+            serde_json::to_string_pretty(&value)? + "\n"
+        }
+    };
+    fs::write(path, output).with_context(|| format!("could not write `{}`", path.to_string_lossy()))
+}
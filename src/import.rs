@@ -0,0 +1,522 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    print,
+    theme::{Style, ThemeValue},
+};
+
+#[derive(ValueEnum, Clone, Copy, Hash, Debug)]
+pub enum ImportFormat {
+    Helix,
+    Vscode,
+    Textmate,
+    Base16,
+}
+
+/// Maps every capture name of the default theme to the scope it is taken from in each of the
+/// supported formats: `(capture, helix scope, TextMate scope, base16 slot)`.
+#[rustfmt::skip]
+const SCOPE_MAP: &[(&str, &str, &str, &str)] = &[
+    ("annotation", "attribute", "storage.type.annotation", "0A"),
+    ("attribute", "attribute", "entity.other.attribute-name", "09"),
+    ("boolean", "constant.builtin.boolean", "constant.language.boolean", "09"),
+    ("character", "constant.character", "constant.character", "0B"),
+    ("comment", "comment", "comment", "03"),
+    ("conditional", "keyword.control.conditional", "keyword.control.conditional", "0E"),
+    ("constant", "constant", "constant.other", "09"),
+    ("constant.builtin", "constant.builtin", "constant.language", "09"),
+    ("constant.macro", "constant", "entity.name.constant", "09"),
+    ("constructor", "constructor", "entity.name.function.constructor", "0D"),
+    ("error", "error", "invalid", "08"),
+    ("exception", "keyword.control.exception", "keyword.control.exception", "0E"),
+    ("field", "variable.other.member", "variable.other.property", "08"),
+    ("float", "constant.numeric.float", "constant.numeric.float", "09"),
+    ("function", "function", "entity.name.function", "0D"),
+    ("function.builtin", "function.builtin", "support.function", "0C"),
+    ("function.macro", "function.macro", "entity.name.function.macro", "0D"),
+    ("include", "keyword.control.import", "keyword.control.import", "0E"),
+    ("keyword", "keyword", "keyword", "0E"),
+    ("keyword.function", "keyword.function", "storage.type.function", "0E"),
+    ("keyword.operator", "keyword.operator", "keyword.operator.word", "0E"),
+    ("label", "label", "entity.name.label", "0A"),
+    ("method", "function.method", "entity.name.function.member", "0D"),
+    ("namespace", "namespace", "entity.name.namespace", "0A"),
+    ("number", "constant.numeric", "constant.numeric", "09"),
+    ("operator", "operator", "keyword.operator", "05"),
+    ("parameter", "variable.parameter", "variable.parameter", "08"),
+    ("property", "variable.other.member", "variable.other.property", "08"),
+    ("punctuation.bracket", "punctuation.bracket", "punctuation.section", "05"),
+    ("punctuation.delimiter", "punctuation.delimiter", "punctuation.separator", "05"),
+    ("punctuation.special", "punctuation.special", "punctuation.definition", "05"),
+    ("repeat", "keyword.control.repeat", "keyword.control.loop", "0E"),
+    ("string", "string", "string", "0B"),
+    ("string.escape", "constant.character.escape", "constant.character.escape", "0C"),
+    ("string.regex", "string.regexp", "string.regexp", "0C"),
+    ("symbol", "string.special.symbol", "constant.other.symbol", "0B"),
+    ("tag", "tag", "entity.name.tag", "08"),
+    ("tag.attribute", "attribute", "entity.other.attribute-name", "09"),
+    ("tag.delimiter", "punctuation.bracket", "punctuation.definition.tag", "05"),
+    ("text.diff.add", "diff.plus", "markup.inserted", "0B"),
+    ("text.diff.delete", "diff.minus", "markup.deleted", "08"),
+    ("text.emphasis", "markup.italic", "markup.italic", "0E"),
+    ("text.literal", "markup.raw", "markup.inline.raw", "0B"),
+    ("text.reference", "markup.link.text", "markup.underline.link", "08"),
+    ("text.strike", "markup.strikethrough", "markup.strikethrough", "08"),
+    ("text.strong", "markup.bold", "markup.bold", "0A"),
+    ("text.title", "markup.heading", "markup.heading", "0D"),
+    ("text.underline", "markup.underline", "markup.underline", "08"),
+    ("text.uri", "markup.link.url", "markup.underline.link", "0C"),
+    ("type", "type", "entity.name.type", "0A"),
+    ("type.builtin", "type.builtin", "support.type", "0A"),
+    ("type.qualifier", "keyword.storage.modifier", "storage.modifier", "0E"),
+    ("variable", "variable", "variable", "08"),
+    ("variable.builtin", "variable.builtin", "variable.language", "08"),
+];
+
+pub fn run(file: &Path, format: Option<ImportFormat>) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => match file.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ImportFormat::Helix,
+            Some("json") => ImportFormat::Vscode,
+            Some("tmTheme") => ImportFormat::Textmate,
+            Some("yaml" | "yml") => ImportFormat::Base16,
+            _ => bail!(
+                "cannot detect theme format of `{}`, please specify `--format`",
+                file.to_string_lossy()
+            ),
+        },
+    };
+    let theme = match format {
+        ImportFormat::Helix => helix(file)?,
+        ImportFormat::Vscode => from_textmate_rules(&vscode(file)?),
+        ImportFormat::Textmate => from_textmate_rules(&textmate(file)?),
+        ImportFormat::Base16 => base16(file)?,
+    };
+    print(
+        &(serde_json::to_string_pretty(&theme).with_context(|| "could not serialize theme")?
+            + "\n"),
+    );
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Could not read theme file at `{}`", path.to_string_lossy()))
+}
+
+/// Normalizes `#rgb`, `#rrggbb` and `#rrggbbaa` colors to the `#rrggbb` form used in themes.
+fn normalize_color(color: &str) -> Option<String> {
+    let hex = color.trim().strip_prefix('#').unwrap_or(color.trim());
+    if !hex.chars().all(|char| char.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        3 => Some(hex.chars().fold(String::from("#"), |mut acc, char| {
+            acc.push(char.to_ascii_lowercase());
+            acc.push(char.to_ascii_lowercase());
+            acc
+        })),
+        6 | 8 => Some(format!("#{}", &hex[..6].to_ascii_lowercase())),
+        _ => None,
+    }
+}
+
+/// A theme entry for a color and font style, entries without a color keep the text color
+fn style(color: Option<String>, font_style: &str) -> Option<ThemeValue> {
+    let words: Vec<_> = font_style.split_whitespace().collect();
+    if words.is_empty() {
+        return color.map(ThemeValue::Color);
+    }
+    Some(ThemeValue::Object(Style {
+        color,
+        underline: words.contains(&"underline"),
+        strikethrough: words.contains(&"strikethrough") || words.contains(&"crossed_out"),
+        italic: words.contains(&"italic"),
+        bold: words.contains(&"bold"),
+        ..Style::default()
+    }))
+}
+
+fn helix(path: &Path) -> Result<BTreeMap<String, ThemeValue>> {
+    let table = helix_table(path)?;
+    let palette = match table.get("palette") {
+        Some(toml::Value::Table(palette)) => palette.clone(),
+        _ => toml::Table::new(),
+    };
+    // palette entries may themselves be named colors
+    let resolve_color = |name: &str| match palette.get(name) {
+        Some(toml::Value::String(color)) => helix_color(color),
+        _ => helix_color(name),
+    };
+
+    let mut theme = BTreeMap::new();
+    for (capture, scope, _, _) in SCOPE_MAP {
+        // helix falls back to less specific scopes by removing trailing components
+        let mut scope = *scope;
+        let value = loop {
+            if let Some(value) = table.get(scope) {
+                break Some(value);
+            }
+            match scope.rsplit_once('.') {
+                Some((parent, _)) => scope = parent,
+                None => break None,
+            }
+        };
+        let value = match value {
+            Some(toml::Value::String(color)) => resolve_color(color).map(ThemeValue::Color),
+            Some(toml::Value::Table(style_table)) => {
+                let color = match style_table.get("fg") {
+                    Some(toml::Value::String(color)) => resolve_color(color),
+                    _ => None,
+                };
+                let modifiers = match style_table.get("modifiers") {
+                    Some(toml::Value::Array(modifiers)) => modifiers
+                        .iter()
+                        .filter_map(toml::Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    _ => String::new(),
+                };
+                style(color, &modifiers)
+            }
+            _ => None,
+        };
+        if let Some(value) = value {
+            theme.insert(capture.to_string(), value);
+        }
+    }
+    Ok(theme)
+}
+
+/// The terminal colors helix themes can refer to by name, with the values of its default palette
+const HELIX_NAMED_COLORS: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("red", "#cd0000"),
+    ("green", "#00cd00"),
+    ("yellow", "#cdcd00"),
+    ("blue", "#0000ee"),
+    ("magenta", "#cd00cd"),
+    ("cyan", "#00cdcd"),
+    ("gray", "#e5e5e5"),
+    ("light-red", "#ff0000"),
+    ("light-green", "#00ff00"),
+    ("light-yellow", "#ffff00"),
+    ("light-blue", "#5c5cff"),
+    ("light-magenta", "#ff00ff"),
+    ("light-cyan", "#00ffff"),
+    ("light-gray", "#7f7f7f"),
+    ("white", "#ffffff"),
+];
+
+/// Resolves a hex color or one of the named terminal colors of helix
+fn helix_color(color: &str) -> Option<String> {
+    match HELIX_NAMED_COLORS.iter().find(|(name, _)| *name == color) {
+        Some((_, hex)) => Some(hex.to_string()),
+        None => normalize_color(color),
+    }
+}
+
+/// Reads a helix theme and merges it over the theme named in its `inherits` key, which is
+/// looked up next to the original file.
+fn helix_table(path: &Path) -> Result<toml::Table> {
+    helix_table_inner(path, &mut vec![])
+}
+
+fn helix_table_inner(path: &Path, visited: &mut Vec<PathBuf>) -> Result<toml::Table> {
+    if visited.iter().any(|visited| visited == path) {
+        bail!(
+            "helix theme `{}` inherits from itself",
+            path.to_string_lossy()
+        );
+    }
+    visited.push(path.to_path_buf());
+    let mut table: toml::Table = toml::from_str(&read(path)?)
+        .with_context(|| format!("invalid helix theme `{}`", path.to_string_lossy()))?;
+    let Some(toml::Value::String(parent)) = table.remove("inherits") else {
+        return Ok(table);
+    };
+    let parent_path = path.with_file_name(format!("{parent}.toml"));
+    let mut parent_table = helix_table_inner(&parent_path, visited).with_context(|| {
+        format!(
+            "could not read inherited helix theme at `{}`",
+            parent_path.to_string_lossy()
+        )
+    })?;
+    for (key, value) in table {
+        match (parent_table.get_mut(&key), value) {
+            (Some(toml::Value::Table(parent_palette)), toml::Value::Table(palette))
+                if key == "palette" =>
+            {
+                parent_palette.extend(palette)
+            }
+            (_, value) => {
+                parent_table.insert(key, value);
+            }
+        }
+    }
+    Ok(parent_table)
+}
+
+struct TmRule {
+    scopes: Vec<String>,
+    foreground: Option<String>,
+    font_style: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TmStyle {
+    foreground: Option<String>,
+    font_style: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmScope {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct TmSetting {
+    scope: Option<TmScope>,
+    settings: TmStyle,
+}
+
+#[derive(Deserialize)]
+struct TmTheme {
+    settings: Vec<TmSetting>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VscodeTokenColors {
+    Path(PathBuf),
+    Rules(Vec<TmSetting>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VscodeTheme {
+    include: Option<PathBuf>,
+    token_colors: Option<VscodeTokenColors>,
+}
+
+fn rules_from_settings(settings: Vec<TmSetting>) -> Vec<TmRule> {
+    settings
+        .into_iter()
+        .filter_map(|setting| {
+            let scopes = match setting.scope? {
+                TmScope::One(scope) => scope.split(',').map(|s| s.trim().to_string()).collect(),
+                TmScope::Many(scopes) => scopes,
+            };
+            Some(TmRule {
+                scopes,
+                foreground: setting
+                    .settings
+                    .foreground
+                    .as_deref()
+                    .and_then(normalize_color),
+                font_style: setting.settings.font_style.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn vscode(path: &Path) -> Result<Vec<TmRule>> {
+    vscode_inner(path, &mut vec![])
+}
+
+fn vscode_inner(path: &Path, visited: &mut Vec<PathBuf>) -> Result<Vec<TmRule>> {
+    if visited.iter().any(|visited| visited == path) {
+        bail!("VS Code theme `{}` includes itself", path.to_string_lossy());
+    }
+    visited.push(path.to_path_buf());
+    // VS Code themes are JSON with comments and trailing commas
+    let theme: VscodeTheme = json5::from_str(&read(path)?)
+        .with_context(|| format!("invalid VS Code theme `{}`", path.to_string_lossy()))?;
+    // rules of the including theme come last so that they take precedence
+    let mut rules = match theme.include {
+        Some(include) => vscode_inner(&path.with_file_name(include), visited)?,
+        None => vec![],
+    };
+    match theme.token_colors {
+        Some(VscodeTokenColors::Path(tm_path)) => {
+            rules.extend(textmate(&path.with_file_name(tm_path))?)
+        }
+        Some(VscodeTokenColors::Rules(settings)) => rules.extend(rules_from_settings(settings)),
+        None => {}
+    }
+    Ok(rules)
+}
+
+fn textmate(path: &Path) -> Result<Vec<TmRule>> {
+    let theme: TmTheme = plist::from_file(path)
+        .with_context(|| format!("invalid TextMate theme `{}`", path.to_string_lossy()))?;
+    Ok(rules_from_settings(theme.settings))
+}
+
+fn from_textmate_rules(rules: &[TmRule]) -> BTreeMap<String, ThemeValue> {
+    let mut theme = BTreeMap::new();
+    for (capture, _, tm_scope, _) in SCOPE_MAP {
+        // like TextMate, pick the rule with the longest selector matching the scope and prefer
+        // later rules on ties, descendant selectors (containing spaces) are not supported
+        let mut best: Option<(usize, &TmRule)> = None;
+        for rule in rules {
+            for selector in &rule.scopes {
+                let matches = !selector.contains(' ')
+                    && (tm_scope == selector
+                        || tm_scope
+                            .strip_prefix(selector.as_str())
+                            .is_some_and(|rest| rest.starts_with('.')));
+                if matches && best.is_none_or(|(len, _)| selector.len() >= len) {
+                    best = Some((selector.len(), rule));
+                }
+            }
+        }
+        if let Some(value) =
+            best.and_then(|(_, rule)| style(rule.foreground.clone(), &rule.font_style))
+        {
+            theme.insert(capture.to_string(), value);
+        }
+    }
+    theme
+}
+
+fn base16(path: &Path) -> Result<BTreeMap<String, ThemeValue>> {
+    let yaml: serde_yaml::Mapping = serde_yaml::from_str(&read(path)?)
+        .with_context(|| format!("invalid base16 scheme `{}`", path.to_string_lossy()))?;
+    // newer schemes nest the colors in a `palette` mapping
+    let palette = match yaml.get("palette") {
+        Some(serde_yaml::Value::Mapping(palette)) => palette,
+        _ => &yaml,
+    };
+
+    let mut theme = BTreeMap::new();
+    for (capture, _, _, slot) in SCOPE_MAP {
+        let key = format!("base{slot}");
+        let color = palette
+            .get(key.as_str())
+            .and_then(serde_yaml::Value::as_str)
+            .and_then(normalize_color)
+            .with_context(|| format!("base16 scheme is missing a valid `{key}` color"))?;
+        theme.insert(
+            capture.to_string(),
+            match *capture {
+                "comment" => ThemeValue::Object(Style {
+                    color: Some(color),
+                    italic: true,
+                    ..Style::default()
+                }),
+                _ => ThemeValue::Color(color),
+            },
+        );
+    }
+    Ok(theme)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Writes a theme file to a directory unique to this test run
+    fn write_theme(name: &str, content: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lirstings-import-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn entry(theme: &BTreeMap<String, ThemeValue>, key: &str) -> Value {
+        serde_json::to_value(&theme[key]).unwrap()
+    }
+
+    #[test]
+    fn imports_vscode_themes_with_comments() {
+        let path = write_theme(
+            "commented.json",
+            r##"{
+                // the token colors of the theme
+                "tokenColors": [
+                    { "scope": "keyword", "settings": { "foreground": "#A626A4", }, },
+                    {
+                        "scope": ["comment", "string"],
+                        /* comments are italic */
+                        "settings": { "foreground": "#aaa", "fontStyle": "italic" },
+                    },
+                ],
+            }"##,
+        );
+        let theme = from_textmate_rules(&vscode(&path).unwrap());
+        assert_eq!(entry(&theme, "keyword"), json!("#a626a4"));
+        assert_eq!(entry(&theme, "comment")["color"], json!("#aaaaaa"));
+        assert_eq!(entry(&theme, "comment")["italic"], json!(true));
+        // `keyword.operator` falls back to the `keyword` selector
+        assert_eq!(entry(&theme, "operator"), json!("#a626a4"));
+    }
+
+    #[test]
+    fn prefers_the_longest_textmate_selector() {
+        let path = write_theme(
+            "selectors.json",
+            r##"{ "tokenColors": [
+                { "scope": "entity.name.function", "settings": { "foreground": "#0000ff" } },
+                { "scope": "entity.name", "settings": { "foreground": "#ff0000" } },
+            ] }"##,
+        );
+        let theme = from_textmate_rules(&vscode(&path).unwrap());
+        assert_eq!(entry(&theme, "function"), json!("#0000ff"));
+        assert_eq!(entry(&theme, "type"), json!("#ff0000"));
+    }
+
+    #[test]
+    fn imports_helix_themes_with_palettes_and_inheritance() {
+        write_theme(
+            "base.toml",
+            "keyword = \"purple\"\n\"function\" = { fg = \"blue\", modifiers = [\"bold\"] }\n\
+             [palette]\npurple = \"#a626a4\"\nblue = \"#4078f2\"\n",
+        );
+        let path = write_theme(
+            "child.toml",
+            "inherits = \"base\"\ncomment = \"light-gray\"\n[palette]\nblue = \"#0000ff\"\n",
+        );
+        let theme = helix(&path).unwrap();
+        assert_eq!(entry(&theme, "keyword"), json!("#a626a4"));
+        assert_eq!(entry(&theme, "function")["color"], json!("#0000ff"));
+        assert_eq!(entry(&theme, "function")["bold"], json!(true));
+        assert_eq!(entry(&theme, "comment"), json!("#7f7f7f"));
+        // `keyword.control.conditional` falls back to `keyword`
+        assert_eq!(entry(&theme, "conditional"), json!("#a626a4"));
+    }
+
+    #[test]
+    fn rejects_self_inheriting_helix_themes() {
+        let path = write_theme("cycle.toml", "inherits = \"cycle\"\n");
+        assert!(helix(&path).is_err());
+    }
+
+    #[test]
+    fn imports_base16_schemes() {
+        let colors: String = (0..16)
+            .map(|slot| format!("base{slot:02X}: \"{slot:02x}{slot:02x}{slot:02x}\"\n"))
+            .collect();
+        let theme = base16(&write_theme("scheme.yaml", &colors)).unwrap();
+        assert_eq!(entry(&theme, "keyword"), json!("#0e0e0e"));
+        assert_eq!(entry(&theme, "comment")["italic"], json!(true));
+        assert!(base16(&write_theme("empty.yaml", "scheme: empty\n")).is_err());
+    }
+}
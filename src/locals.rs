@@ -0,0 +1,189 @@
+use std::{collections::HashMap, ops::Range};
+
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::theme;
+
+/// Theme keys for the definitions of parameters and local variables and the references to them.
+/// Like all capture names they fall back to less specific keys, so `variable.local.reference`
+/// uses `variable` if the theme has neither `variable.local.reference` nor `variable.local`.
+const PARAMETER: &str = "parameter";
+const PARAMETER_REFERENCE: &str = "parameter.reference";
+const LOCAL_VARIABLE: &str = "variable.local";
+const LOCAL_VARIABLE_REFERENCE: &str = "variable.local.reference";
+
+enum Local<'code> {
+    Scope,
+    /// `keys` are the theme keys of the definition and its references if it is colored
+    Definition {
+        name: &'code str,
+        keys: Option<(&'static str, &'static str)>,
+    },
+    Reference {
+        name: &'code str,
+    },
+}
+
+/// Resolves the references in the locals query to the parameters and variables they refer to and
+/// returns the theme key for each of them sorted by position. Every binding gets its own
+/// `rainbow.<n>` key instead if `rainbow_keys` is not zero, cycling through that many keys.
+///
+/// Both the capture names of tree-sitter (`@local.scope`, `@local.definition`,
+/// `@local.reference`) and those of older nvim-treesitter queries (`@scope`, `@definition.var`,
+/// `@reference`) are understood. Only definitions with the kinds `parameter`, `var` or
+/// `variable` or without a kind are colored, other kinds like functions just shadow outer ones.
+pub fn keys(
+    query: &Query,
+    locals_patterns: Range<usize>,
+    tree: &Tree,
+    code: &str,
+    rainbow_keys: usize,
+) -> Vec<(Range<usize>, String)> {
+    let mut locals = vec![];
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(query, tree.root_node(), code.as_bytes()) {
+        if !locals_patterns.contains(&query_match.pattern_index) {
+            continue;
+        }
+        for capture in query_match.captures {
+            let name = &query.capture_names()[capture.index as usize];
+            let name = name.strip_prefix("local.").unwrap_or(name);
+            let text = &code[capture.node.byte_range()];
+            let local = match name.split_once('.').unwrap_or((name, "")) {
+                ("scope", _) => Local::Scope,
+                // other kinds of definitions, like functions, still shadow outer bindings
+                ("definition", kind) => Local::Definition {
+                    name: text,
+                    keys: match kind {
+                        "parameter" => Some((PARAMETER, PARAMETER_REFERENCE)),
+                        "" | "var" | "variable" => Some((LOCAL_VARIABLE, LOCAL_VARIABLE_REFERENCE)),
+                        _ => None,
+                    },
+                },
+                ("reference", _) => Local::Reference { name: text },
+                _ => continue,
+            };
+            locals.push((capture.node.byte_range(), local));
+        }
+    }
+    // outer scopes first, definitions before the references matching the same node
+    locals.sort_by_key(|(range, local)| {
+        let order = match local {
+            Local::Scope => 0,
+            Local::Definition { .. } => 1,
+            Local::Reference { .. } => 2,
+        };
+        (range.start, usize::MAX - range.end, order)
+    });
+
+    let mut keys = vec![];
+    // the end of each open scope with the bindings defined in it as `(key, rainbow index)`
+    let mut scopes = vec![(usize::MAX, HashMap::<_, Option<(&str, usize)>>::new())];
+    let mut bindings = 0;
+    let mut last_definition = None;
+    for (range, local) in locals {
+        while scopes.len() > 1 && scopes[scopes.len() - 1].0 <= range.start {
+            scopes.pop();
+        }
+        match local {
+            Local::Scope => scopes.push((range.end, HashMap::new())),
+            Local::Definition {
+                name,
+                keys: definition_keys,
+            } => {
+                let binding = definition_keys.map(|(key, reference_key)| {
+                    keys.push((range.clone(), binding_key(key, bindings, rainbow_keys)));
+                    bindings += 1;
+                    (reference_key, bindings - 1)
+                });
+                let (_, scope) = scopes.last_mut().expect("the root scope is never popped");
+                scope.insert(name, binding);
+                last_definition = Some(range);
+            }
+            // the node of a definition is usually matched by a reference pattern as well
+            Local::Reference { .. } if last_definition.as_ref() == Some(&range) => {}
+            Local::Reference { name } => {
+                let binding = scopes
+                    .iter()
+                    .rev()
+                    .find_map(|(_, scope)| scope.get(name))
+                    .copied()
+                    .flatten();
+                if let Some((key, index)) = binding {
+                    keys.push((range, binding_key(key, index, rainbow_keys)));
+                }
+            }
+        }
+    }
+    keys
+}
+
+fn binding_key(key: &str, index: usize, rainbow_keys: usize) -> String {
+    match rainbow_keys {
+        0 => key.to_string(),
+        _ => theme::rainbow_key(index, rainbow_keys),
+    }
+}
+
+// the tests need a grammar, which is only available without setup when it is bundled
+#[cfg(all(test, feature = "bundled-grammars"))]
+mod tests {
+    use super::*;
+    use crate::ts;
+
+    const QUERY: &str = r#"
+        [(function_item) (block)] @local.scope
+        (parameter pattern: (identifier) @local.definition.parameter)
+        (let_declaration pattern: (identifier) @local.definition)
+        (function_item name: (identifier) @local.definition.function)
+        (identifier) @local.reference
+    "#;
+
+    /// The keys as `(text, key)` pairs
+    fn keys(code: &str, rainbow_keys: usize) -> Vec<(&str, String)> {
+        let lang = tree_sitter_rust::language();
+        let query = Query::new(lang, QUERY).unwrap();
+        let tree = ts::parse(lang, code).unwrap();
+        super::keys(&query, 0..query.pattern_count(), &tree, code, rainbow_keys)
+            .into_iter()
+            .map(|(range, key)| (&code[range], key))
+            .collect()
+    }
+
+    #[test]
+    fn colors_parameters_and_local_variables() {
+        let keys = keys("fn f(a: i32) -> i32 { let b = a; f(b) }", 0);
+        let expected = [
+            ("a", PARAMETER),
+            ("b", LOCAL_VARIABLE),
+            ("a", PARAMETER_REFERENCE),
+            ("b", LOCAL_VARIABLE_REFERENCE),
+        ];
+        assert_eq!(keys, expected.map(|(text, key)| (text, key.to_string())));
+    }
+
+    #[test]
+    fn resolves_references_in_their_scope() {
+        let keys = keys("fn f(a: i32) { { let a = 1; a; } a; }", 0);
+        let expected = [
+            ("a", PARAMETER),
+            ("a", LOCAL_VARIABLE),
+            ("a", LOCAL_VARIABLE_REFERENCE),
+            ("a", PARAMETER_REFERENCE),
+        ];
+        assert_eq!(keys, expected.map(|(text, key)| (text, key.to_string())));
+    }
+
+    #[test]
+    fn gives_each_binding_a_rainbow_key() {
+        let keys = keys("fn f(a: i32, b: i32, c: i32) { c; a; }", 2);
+        let expected = [
+            ("a", "rainbow.1"),
+            ("b", "rainbow.2"),
+            ("c", "rainbow.1"),
+            ("c", "rainbow.1"),
+            ("a", "rainbow.1"),
+        ];
+        assert_eq!(keys, expected.map(|(text, key)| (text, key.to_string())));
+    }
+}
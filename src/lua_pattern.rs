@@ -0,0 +1,187 @@
+use std::{iter::Peekable, str::Chars};
+
+use anyhow::{bail, Result};
+
+/// Translates a Lua pattern, as used by Neovim's `#lua-match?`, to an equivalent regular
+/// expression for the `regex` crate. Balanced matches (`%b`), frontiers (`%f`) and back
+/// references (`%1`) have no equivalent and are reported as errors.
+pub fn to_regex(pattern: &str) -> Result<String> {
+    // `.` matches any character in Lua, including newlines
+    let mut out = String::from("(?s)");
+    let mut chars = pattern.chars().peekable();
+    let mut open_captures = 0;
+    if chars.next_if_eq(&'^').is_some() {
+        out.push('^');
+    }
+    while let Some(char) = chars.next() {
+        let item = match char {
+            '(' => {
+                open_captures += 1;
+                out.push('(');
+                continue;
+            }
+            ')' => {
+                if open_captures == 0 {
+                    bail!("invalid pattern capture, `)` without matching `(`");
+                }
+                open_captures -= 1;
+                out.push(')');
+                continue;
+            }
+            '$' if chars.peek().is_none() => {
+                out.push('$');
+                continue;
+            }
+            '.' => ".".to_string(),
+            '[' => set(&mut chars)?,
+            '%' => match chars.next() {
+                Some('b') => bail!("balanced matches (`%b`) cannot be translated to a regex"),
+                Some('f') => bail!("frontier patterns (`%f`) cannot be translated to a regex"),
+                Some(digit @ '0'..='9') => {
+                    bail!("back references (`%{digit}`) cannot be translated to a regex")
+                }
+                Some(class) => match class_ranges(class)? {
+                    Some((ranges, false)) => format!("[{ranges}]"),
+                    Some((ranges, true)) => format!("[^{ranges}]"),
+                    None => regex::escape(&class.to_string()),
+                },
+                None => bail!("malformed pattern, ends with `%`"),
+            },
+            char => regex::escape(&char.to_string()),
+        };
+        out.push_str(&item);
+        // quantifiers only apply to single character classes
+        match chars.peek() {
+            Some('*') => out.push('*'),
+            Some('+') => out.push('+'),
+            Some('-') => out.push_str("*?"),
+            Some('?') => out.push('?'),
+            _ => continue,
+        }
+        chars.next();
+    }
+    if open_captures > 0 {
+        bail!("unfinished capture, `(` without matching `)`");
+    }
+    Ok(out)
+}
+
+/// Translates the rest of a `[set]` after its opening bracket
+fn set(chars: &mut Peekable<Chars>) -> Result<String> {
+    let mut out = String::from("[");
+    if chars.next_if_eq(&'^').is_some() {
+        out.push('^');
+    }
+    let mut first = true;
+    loop {
+        let char = match chars.next() {
+            Some(']') if !first => break,
+            Some(char) => char,
+            None => bail!("malformed pattern, missing `]`"),
+        };
+        first = false;
+        match char {
+            '%' => match chars.next() {
+                Some(class) => match class_ranges(class)? {
+                    Some((ranges, false)) => out.push_str(ranges),
+                    Some((ranges, true)) => out.push_str(&format!("[^{ranges}]")),
+                    None => out.push_str(&escape_in_set(class)),
+                },
+                None => bail!("malformed pattern, missing `]`"),
+            },
+            char if chars.peek() == Some(&'-') => {
+                chars.next();
+                match chars.next_if(|&end| end != ']') {
+                    Some(end) => out += &format!("{}-{}", escape_in_set(char), escape_in_set(end)),
+                    // a trailing `-` is literal
+                    None => out += &format!("{}{}", escape_in_set(char), escape_in_set('-')),
+                }
+            }
+            char => out.push_str(&escape_in_set(char)),
+        }
+    }
+    out.push(']');
+    Ok(out)
+}
+
+/// The regex class ranges of a Lua class letter and whether the class is complemented, `None`
+/// for characters that are escaped with `%` to be matched literally
+fn class_ranges(class: char) -> Result<Option<(&'static str, bool)>> {
+    let ranges = match class.to_ascii_lowercase() {
+        'a' => "a-zA-Z",
+        'c' => r"\x00-\x1F\x7F",
+        'd' => "0-9",
+        'g' => r"\x21-\x7E",
+        'l' => "a-z",
+        'p' => r##"!-/:-@\[-`{-~"##,
+        's' => r"\t\n\x0B\f\r ",
+        'u' => "A-Z",
+        'w' => "a-zA-Z0-9",
+        'x' => "0-9a-fA-F",
+        'z' => r"\x00",
+        _ if class.is_ascii_alphanumeric() => bail!("unknown character class `%{class}`"),
+        _ => return Ok(None),
+    };
+    Ok(Some((ranges, class.is_ascii_uppercase())))
+}
+
+/// Escapes characters which have a special meaning inside of regex classes
+fn escape_in_set(char: char) -> String {
+    match char {
+        '\\' | '[' | ']' | '^' | '-' | '&' | '~' => format!("\\{char}"),
+        char => char.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+
+    fn regex(pattern: &str) -> Regex {
+        Regex::new(&to_regex(pattern).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn translates_classes_and_quantifiers() {
+        assert_eq!(to_regex("^%u%l+$").unwrap(), "(?s)^[A-Z][a-z]+$");
+        assert_eq!(to_regex("%D-").unwrap(), "(?s)[^0-9]*?");
+        let snake_case = regex("^[%l_][%w_]*$");
+        assert!(snake_case.is_match("snake_case_2"));
+        assert!(!snake_case.is_match("CamelCase"));
+    }
+
+    #[test]
+    fn escapes_literal_characters() {
+        let dotted = regex("^%.%.%.$");
+        assert!(dotted.is_match("..."));
+        assert!(!dotted.is_match("abc"));
+        assert!(regex("a|b").is_match("a|b"));
+        assert!(!regex("a|b").is_match("a"));
+        assert!(regex("[%]-]").is_match("-"));
+        assert!(regex("^[a-]$").is_match("-"));
+    }
+
+    #[test]
+    fn only_anchors_at_the_ends() {
+        assert!(regex("a^b$c").is_match("a^b$c"));
+        assert!(!regex("^b").is_match("ab"));
+    }
+
+    #[test]
+    fn rejects_untranslatable_patterns() {
+        assert!(to_regex("%b()").is_err());
+        assert!(to_regex("%f[%w]").is_err());
+        assert!(to_regex("(a)%1").is_err());
+        assert!(to_regex("%q").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(to_regex("(a").is_err());
+        assert!(to_regex("a)").is_err());
+        assert!(to_regex("[a").is_err());
+        assert!(to_regex("a%").is_err());
+    }
+}
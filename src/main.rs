@@ -1,26 +1,39 @@
-// TODO: write README.md
 use std::{
     env, fs,
     io::{self, Write},
     iter,
+    ops::RangeInclusive,
     path::{Path, PathBuf},
     process,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 
-use cache::{CACHE_FILE_PATH, CACHE_SKIP_MESSAGE, CACHE_WRITE_MESSAGE};
+use cache::{CACHE_SKIP_MESSAGE, CACHE_WRITE_MESSAGE};
 use config::CONFIG_FILE_PATH;
-use range::Range;
+use elide::ElideSpec;
+use range::{Range, Ranges};
 
-use crate::{config::Config, output::Output};
+use crate::{color::PrintMode, config::Config, import::ImportFormat, output::Output};
 
 mod ansi;
+mod brackets;
+mod bundled;
 mod cache;
+mod color;
 mod config;
+mod doctor;
+mod elide;
 mod from_tex;
+mod grammar;
+mod import;
+mod locals;
+mod lua_pattern;
+mod outline;
 mod output;
+mod preview;
+mod query;
 mod range;
 mod theme;
 mod ts;
@@ -31,6 +44,27 @@ pub struct Cli {
     #[arg(short = 'x', long, global = true, default_value = "")]
     fancyvrb_args: String,
 
+    /// Name of a theme from the `themes` config section to use instead of `theme`
+    #[arg(short, long, global = true)]
+    theme: Option<String>,
+
+    /// Adapt the theme for printing without colors
+    #[arg(long, global = true)]
+    print_mode: Option<PrintMode>,
+
+    /// Project config file to use instead of the first `lirstings.json` found in the current
+    /// directory or its ancestors
+    #[arg(short, long, global = true, env = "LIRSTINGS_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Use the embedded default config if there is no project config instead of creating one
+    #[arg(long, global = true, env = "LIRSTINGS_DEFAULT_CONFIG")]
+    default_config: bool,
+
+    /// Fail instead of warning if the highlighted code contains syntax errors
+    #[arg(long, global = true)]
+    strict: bool,
+
     #[command(subcommand)]
     subcommand: Command,
 }
@@ -47,14 +81,49 @@ pub enum Command {
         #[arg(long)]
         raw_queries: bool,
 
-        #[arg(short = 'R', long, value_delimiter = ',')]
-        ranges: Vec<Range>,
+        /// Lines to show, like `5-10`, `3:4-7:12`, `12-`, `-5-`, `$-10-$`, `42` or
+        /// `/^fn main/../^}/`, separated by commas
+        #[arg(short = 'R', long, allow_hyphen_values = true)]
+        ranges: Vec<Ranges>,
+
+        /// Node types whose inner lines are replaced by a placeholder, like `block` or
+        /// `match_arm>1` for nodes nested in more than one node of the same type
+        #[arg(long, value_delimiter = ',')]
+        elide: Vec<ElideSpec>,
 
         #[arg(short, long)]
         filename_strip_prefix: Option<PathBuf>,
+
+        /// Read the file as it was at a git revision, which can also be given as `file@rev`
+        #[arg(long)]
+        rev: Option<String>,
+
+        /// Append the revision to the file name label
+        #[arg(long)]
+        show_rev: bool,
+    },
+    /// List only the definitions found by the grammar's `tags.scm`, leaving out function bodies
+    Outline {
+        file: PathBuf,
+
+        #[arg(short, long)]
+        filename_strip_prefix: Option<PathBuf>,
+
+        /// Read the file as it was at a git revision, which can also be given as `file@rev`
+        #[arg(long)]
+        rev: Option<String>,
+
+        /// Append the revision to the file name label
+        #[arg(long)]
+        show_rev: bool,
     },
     Inline {
         file_ext: String,
+
+        /// Name of the `inline_templates` entry wrapping the code, like `expression` or `type`
+        #[arg(long)]
+        context: Option<String>,
+
         code: Vec<String>,
     },
     Ansi {
@@ -66,122 +135,203 @@ pub enum Command {
         file: PathBuf,
         args: String,
     },
+    Theme {
+        #[command(subcommand)]
+        subcommand: ThemeCommand,
+    },
+    /// Validate the config file, list the installed grammars and check that their queries compile
+    Doctor,
+    Config {
+        #[command(subcommand)]
+        subcommand: ConfigCommand,
+    },
+    /// Manage the grammars in `parser_search_dirs` and their versions in `lirstings.lock.json`
+    Grammar {
+        #[command(subcommand)]
+        subcommand: GrammarCommand,
+    },
+}
+
+#[derive(Subcommand, Hash)]
+pub enum GrammarCommand {
+    /// Add a local grammar repository to the project config, build it and lock its version
+    Add { path: PathBuf },
+    /// List all grammars with their current revision and lock status
+    List,
+    /// Compile grammars ahead of time and record their versions in the lock file
+    Build {
+        /// Only build these grammars
+        names: Vec<String>,
+
+        /// Fail instead of updating the lock file if a grammar differs from the locked version
+        #[arg(long)]
+        locked: bool,
+    },
+    /// Remove a grammar from the project config and the lock file
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Hash)]
+pub enum ConfigCommand {
+    /// Print a JSON Schema for config files
+    Schema,
+}
+
+#[derive(Subcommand, Hash)]
+pub enum ThemeCommand {
+    /// Convert a Helix, VS Code, TextMate or base16 theme to a lirstings theme map
+    Import {
+        file: PathBuf,
+
+        #[arg(short, long)]
+        format: Option<ImportFormat>,
+    },
+    /// Report the WCAG contrast ratio of every theme entry
+    Check {
+        /// Background color to check against, entries with their own background use that instead
+        #[arg(short, long, default_value = "#ffffff")]
+        background: String,
+    },
+    /// Render every theme entry and a sample for each installed grammar to a `.tex` or `.html` file
+    Preview {
+        #[arg(short, long, default_value = "lirstings-preview.tex")]
+        output: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
     run(Cli::parse())
 }
 
-fn run(cli: Cli) -> Result<()> {
-    let mut config = Config::read()
-        .with_context(|| format!("could not read or create config file at `{CONFIG_FILE_PATH}`"))?
+fn run(mut cli: Cli) -> Result<()> {
+    split_revision(&mut cli.subcommand);
+    if let Command::Theme {
+        subcommand: ThemeCommand::Import { file, format },
+    } = &cli.subcommand
+    {
+        return import::run(file, *format);
+    }
+    if let Command::Doctor = &cli.subcommand {
+        return doctor::run(&cli);
+    }
+    if let Command::Config {
+        subcommand: ConfigCommand::Schema,
+    } = &cli.subcommand
+    {
+        print(&(Config::schema() + "\n"));
+        return Ok(());
+    }
+
+    if let Command::TexInclude = &cli.subcommand {
+        // the package works without a config, so none is created and the default is used
+        let config = Config::read(cli.config.as_deref(), true)
+            .with_context(|| "could not read config file")?
+            .expect("the embedded default is used without config files");
+        return tex_include(&config, &cli);
+    }
+
+    let mut config = Config::read(cli.config.as_deref(), cli.default_config)
+        .with_context(|| "could not read or create config file")?
         .unwrap_or_else(|| {
             eprintln!("New configuration file was created at `{CONFIG_FILE_PATH}`");
             process::exit(200);
         });
+    if let Command::Grammar { subcommand } = &cli.subcommand {
+        return grammar::run(&config, &cli, subcommand);
+    }
+    config.select_theme(cli.theme.as_deref())?;
     config
         .resolve_links()
         .with_context(|| "invalid config file")?;
+    if let Some(mode) = cli.print_mode {
+        color::apply_print_mode(&mut config.theme, mode);
+    }
 
-    let mut cache = cache::read()
-        .with_context(|| format!("could not read or create cache file at `{CACHE_FILE_PATH}`"))?;
-
-    let (mut code, line_numbers) = match &cli.subcommand {
-        Command::TexInclude => {
-            print(
-                &include_str!("./lirstings.tex").replace(
-                    "EXECUTABLE",
-                    &env::current_exe()
-                        .as_ref()
-                        .map(|path| path.to_string_lossy())
-                        .unwrap_or("lirstings".into())
-                        .replace('\'', "'\"'\"'"),
-                ),
-            );
-            return Ok(());
+    if let Command::Theme {
+        subcommand: ThemeCommand::Check { background },
+    } = &cli.subcommand
+    {
+        return color::check(&config.theme, background);
+    }
+    if let Command::Theme {
+        subcommand: ThemeCommand::Preview { output },
+    } = &cli.subcommand
+    {
+        return preview::run(&config, &cli, output);
+    }
+
+    let mut cache = cache::read(&config.cache_file).with_context(|| {
+        format!(
+            "could not read or create cache file at `{}`",
+            config.cache_file.to_string_lossy()
+        )
+    })?;
+
+    // the whole file the code is taken from, used to check it for syntax errors
+    let (mut code, original, line_numbers) = match &cli.subcommand {
+        Command::TexInclude => unreachable!("`tex-include` subcommand immediately returns"),
+        Command::FromTex { file, args } => return from_tex::run(file, args, &cli),
+        Command::Theme { .. } => unreachable!("`theme` subcommands immediately return"),
+        Command::Doctor => unreachable!("`doctor` subcommand immediately returns"),
+        Command::Config { .. } => unreachable!("`config` subcommands immediately return"),
+        Command::Grammar { .. } => unreachable!("`grammar` subcommands immediately return"),
+        Command::TreeSitter {
+            file,
+            ranges,
+            elide,
+            rev,
+            ..
+        } if ranges.is_empty() && elide.is_empty() => {
+            let raw = read_code(file, rev.as_deref(), &config)?;
+            (raw.clone(), Some(raw), None)
         }
-        Command::FromTex { file, args } => return from_tex::run(file, args),
-        Command::TreeSitter { file, ranges, .. } if ranges.is_empty() => (read_file(file)?, None),
-        Command::Ansi { file } => (read_file(file)?, None),
-        Command::TreeSitter { file, ranges, .. } => {
-            let raw = read_file(file)?;
+        Command::Ansi { file } => (
+            range::expand_tabs(&read_file(file)?, config.tab_width),
+            None,
+            None,
+        ),
+        Command::TreeSitter {
+            file,
+            ranges,
+            elide,
+            rev,
+            ..
+        } => {
+            let raw = read_code(file, rev.as_deref(), &config)?;
             let lines: Vec<_> = raw.lines().collect();
-            let comment_style = file
-                .extension()
-                .and_then(|ext| config.comment_map.get(ext.to_string_lossy().as_ref()));
-            let mut code = String::new();
-            let mut line_numbers = vec![];
-            let mut prev_range = Range::default();
-            // TODO: prevent panics during indexing
-            for (index, range) in ranges.iter().enumerate() {
-                let mut range_offset = 0;
-                if index != 0 {
-                    if range.inline {
-                        // remove previous newline
-                        code.truncate(code.len() - 1);
-
-                        // add comment and following line
-                        code += comment_style.map_or("/*", |style| &style.block.0);
-                        code += " ... ";
-                        code += comment_style.map_or("*/", |style| &style.block.1);
-                        code += match range.start_col {
-                            Some(col) => &lines[range.start][col..],
-                            None => lines[range.start].trim_start(),
-                        };
-                        code.push('\n');
-
-                        // set range offset
-                        range_offset = 1;
-                    } else {
-                        // take the larger indent from...
-                        let indent = range.indent_offset
-                            + usize::max(
-                                // ...the last line of the previous range and...
-                                lines[prev_range.end]
-                                    .chars()
-                                    .take_while(|char| *char == ' ')
-                                    .count(),
-                                // ...the first line of the following range.
-                                lines[range.start]
-                                    .chars()
-                                    .take_while(|char| *char == ' ')
-                                    .count(),
-                            );
-                        code += &format!(
-                            "{}{} ...\n",
-                            " ".repeat(indent),
-                            comment_style.map_or("//", |style| &style.line)
-                        );
-                        line_numbers.push(0..=0);
-                    }
+            let ranges = range::resolve(ranges.iter().flat_map(|ranges| &ranges.0), &lines)
+                .with_context(|| format!("invalid range for `{}`", file.to_string_lossy()))?;
+            let ranges = match elide.is_empty() {
+                true => ranges,
+                false => {
+                    let settings = ts::get_settings(config.clone(), &cli.subcommand)?;
+                    let hidden = elide::hidden_rows(&ts::parse(settings.lang, &raw)?, elide);
+                    let whole_file = [Range {
+                        end: lines.len().saturating_sub(1),
+                        ..Range::default()
+                    }];
+                    let ranges = match ranges.is_empty() {
+                        true => &whole_file[..],
+                        false => &ranges[..],
+                    };
+                    elide::split_ranges(ranges, &hidden, &lines)
                 }
-                code += &lines
-                    .get(range.start + range_offset..=range.end)
-                    .with_context(|| "range out of bounds for input file")?
-                    .iter()
-                    .enumerate()
-                    .map(
-                        |(index, line)| match (index, range.start_col, range.end_col) {
-                            (0, Some(col), _) if range_offset == 0 => &line[col..],
-                            (idx, _, Some(col))
-                                if idx == range.end - range.start + range_offset =>
-                            {
-                                &line[..col]
-                            }
-                            _ => line,
-                        },
-                    )
-                    .fold(String::new(), |mut acc, line| {
-                        acc += line;
-                        acc.push('\n');
-                        acc
-                    });
-                line_numbers.push(range.start + range_offset + 1..=range.end + 1);
-                prev_range = *range;
-            }
-            (code, Some(line_numbers))
+            };
+            let (code, line_numbers) = join_ranges(&raw, file, &ranges, &config)?;
+            (code, Some(raw), Some(line_numbers))
+        }
+        Command::Outline { file, rev, .. } => {
+            let raw = read_code(file, rev.as_deref(), &config)?;
+            let settings = ts::get_settings(config.clone(), &cli.subcommand)?;
+            let ranges = outline::ranges(&settings, &raw)?;
+            let (code, line_numbers) = join_ranges(&raw, file, &ranges, &config)?;
+            (code, Some(raw), Some(line_numbers))
         }
-        Command::Inline { code, .. } => (code.join(" "), None),
+        Command::Inline { code, .. } => (
+            range::expand_tabs(&code.join(" "), config.tab_width),
+            None,
+            None,
+        ),
     };
     let gobble = code
         .lines()
@@ -200,6 +350,10 @@ fn run(cli: Cli) -> Result<()> {
     let (output, hash) = match &cli.subcommand {
         Command::TexInclude => unreachable!("`tex-include` subcommand immediately returns"),
         Command::FromTex { .. } => unreachable!("`from-tex` subcommand immediately returns"),
+        Command::Theme { .. } => unreachable!("`theme` subcommands immediately return"),
+        Command::Doctor => unreachable!("`doctor` subcommand immediately returns"),
+        Command::Config { .. } => unreachable!("`config` subcommands immediately return"),
+        Command::Grammar { .. } => unreachable!("`grammar` subcommands immediately return"),
         Command::Ansi { .. } => {
             let hash = cache::hash(&cli, &code, &config, None);
             if let Some(cached) = cache.get_cached(hash) {
@@ -216,15 +370,15 @@ fn run(cli: Cli) -> Result<()> {
             raw,
             file,
             filename_strip_prefix,
+            rev,
+            show_rev,
             ..
         } => {
-            let filename = match filename_strip_prefix {
-                Some(prefix) => file
-                    .strip_prefix(prefix)
-                    .ok()
-                    .map(|path| path.to_string_lossy().into_owned()),
-                None => None,
-            };
+            let filename = label(
+                file,
+                filename_strip_prefix.as_deref(),
+                rev.as_deref().filter(|_| *show_rev),
+            );
             if *raw {
                 let hash = cache::hash(&cli, &code, &config, None);
                 if let Some(cached) = cache.get_cached(hash) {
@@ -255,11 +409,52 @@ fn run(cli: Cli) -> Result<()> {
                     return Ok(());
                 }
                 (
-                    ts::highlight(&code, line_numbers, &cli, settings, filename)?,
+                    ts::highlight(
+                        &code,
+                        original.as_deref(),
+                        line_numbers,
+                        &cli,
+                        settings,
+                        filename,
+                    )?,
                     hash,
                 )
             }
         }
+        Command::Outline {
+            file,
+            filename_strip_prefix,
+            rev,
+            show_rev,
+        } => {
+            let filename = label(
+                file,
+                filename_strip_prefix.as_deref(),
+                rev.as_deref().filter(|_| *show_rev),
+            );
+            let settings = ts::get_settings(config.clone(), &cli.subcommand)?;
+            let hash_query = settings.highlights_query.clone()
+                + &settings.injection_query
+                + &settings.locals_query
+                + &settings.tags_query;
+            let hash = cache::hash(&cli, &code, &config, Some(hash_query));
+            if let Some(cached) = cache.get_cached(hash) {
+                eprintln!("{CACHE_SKIP_MESSAGE}");
+                print(cached);
+                return Ok(());
+            }
+            (
+                ts::highlight(
+                    &code,
+                    original.as_deref(),
+                    line_numbers,
+                    &cli,
+                    settings,
+                    filename,
+                )?,
+                hash,
+            )
+        }
         Command::Inline { .. } => {
             let settings = ts::get_settings(config.clone(), &cli.subcommand)?;
             let hash_query = settings.highlights_query.clone()
@@ -272,7 +467,14 @@ fn run(cli: Cli) -> Result<()> {
                 return Ok(());
             }
             (
-                ts::highlight(&code, line_numbers, &cli, settings, None)?,
+                ts::highlight(
+                    &code,
+                    original.as_deref(),
+                    line_numbers,
+                    &cli,
+                    settings,
+                    None,
+                )?,
                 hash,
             )
         }
@@ -280,18 +482,248 @@ fn run(cli: Cli) -> Result<()> {
     print(&output);
     eprintln!("{CACHE_WRITE_MESSAGE}");
     cache
-        .set_entry(hash, output)
+        .set_entry(&config.cache_file, hash, output)
         .with_context(|| "could not update cache file")?;
 
     Ok(())
 }
 
+/// Joins the lines of `ranges`, separated by placeholder comments, and returns the line number in
+/// the file of each joined line, or zero for placeholders
+fn join_ranges(
+    raw: &str,
+    file: &Path,
+    ranges: &[Range],
+    config: &Config,
+) -> Result<(String, Vec<RangeInclusive<usize>>)> {
+    let lines: Vec<_> = raw.lines().collect();
+    let comment_style = file
+        .extension()
+        .and_then(|ext| config.comment_map.get(ext.to_string_lossy().as_ref()));
+    let mut code = String::new();
+    let mut line_numbers = vec![];
+    if let Some(range) = ranges.iter().find(|range| range.end >= lines.len()) {
+        bail!(
+            "range {}-{} is out of bounds for `{}`, which has {} lines",
+            range.start + 1,
+            range.end + 1,
+            file.to_string_lossy(),
+            lines.len()
+        );
+    }
+    // the byte index of a column of a line
+    let column = |row: usize, col: usize| {
+        let unit = config.column_unit;
+        unit.byte_index(lines[row], col).with_context(|| {
+            format!(
+                "column {col} is out of bounds for line {} of `{}`, which has {} {unit}",
+                row + 1,
+                file.to_string_lossy(),
+                unit.width(lines[row]),
+            )
+        })
+    };
+    let mut prev_range = Range::default();
+    for (index, range) in ranges.iter().enumerate() {
+        let mut range_offset = 0;
+        if index != 0 {
+            if range.inline {
+                // remove previous newline
+                code.truncate(code.len() - 1);
+
+                // add comment and following line
+                code += comment_style.map_or("/*", |style| &style.block.0);
+                code += " ... ";
+                code += comment_style.map_or("*/", |style| &style.block.1);
+                code += match range.start_col {
+                    Some(col) => &lines[range.start][column(range.start, col)?..],
+                    None => lines[range.start].trim_start(),
+                };
+                code.push('\n');
+
+                // set range offset
+                range_offset = 1;
+            } else {
+                // take the larger indent from...
+                let indent = range.indent_offset
+                    + usize::max(
+                        // ...the last line of the previous range and...
+                        lines[prev_range.end]
+                            .chars()
+                            .take_while(|char| *char == ' ')
+                            .count(),
+                        // ...the first line of the following range.
+                        lines[range.start]
+                            .chars()
+                            .take_while(|char| *char == ' ')
+                            .count(),
+                    );
+                code += &format!(
+                    "{}{} ...\n",
+                    " ".repeat(indent),
+                    comment_style.map_or("//", |style| &style.line)
+                );
+                line_numbers.push(0..=0);
+            }
+        }
+        for (row, line) in lines
+            .iter()
+            .enumerate()
+            .take(range.end + 1)
+            .skip(range.start + range_offset)
+        {
+            let start = match range.start_col {
+                Some(col) if row == range.start => column(row, col)?,
+                _ => 0,
+            };
+            let end = match range.end_col {
+                Some(col) if row == range.end => column(row, col)?,
+                _ => line.len(),
+            };
+            code += &line[start..end.max(start)];
+            code.push('\n');
+        }
+        line_numbers.push(range.start + range_offset + 1..=range.end + 1);
+        prev_range = *range;
+    }
+    Ok((code, line_numbers))
+}
+
+fn tex_include(config: &Config, cli: &Cli) -> Result<()> {
+    let mut tex = include_str!("./lirstings.tex").replace(
+        "EXECUTABLE",
+        &env::current_exe()
+            .as_ref()
+            .map(|path| path.to_string_lossy())
+            .unwrap_or("lirstings".into())
+            .replace('\'', "'\"'\"'"),
+    );
+    if config.semantic_styles {
+        for name in config.themes.keys() {
+            let mut named = config.clone();
+            named.select_theme(Some(name))?;
+            named
+                .resolve_links()
+                .with_context(|| format!("invalid theme `{name}`"))?;
+            if let Some(mode) = cli.print_mode {
+                color::apply_print_mode(&mut named.theme, mode);
+            }
+            tex += &format!(
+                "\\expandafter\\def\\csname lirst@theme@{name}\\endcsname{{%\n{}}}\n",
+                theme::tex_definitions(&named.theme, "##")
+            );
+        }
+        let mut selected = config.clone();
+        selected.select_theme(cli.theme.as_deref())?;
+        selected
+            .resolve_links()
+            .with_context(|| "invalid config file")?;
+        if let Some(mode) = cli.print_mode {
+            color::apply_print_mode(&mut selected.theme, mode);
+        }
+        tex += &theme::tex_definitions(&selected.theme, "#");
+    }
+    print(&tex);
+    Ok(())
+}
+
 #[inline]
 fn print(input: &str) {
     let mut stdout = io::stdout().lock();
     _ = stdout.write_all(input.as_bytes());
 }
 
+/// The label of the listing: the path of `file` relative to `prefix`, followed by the revision
+/// if it is shown
+fn label(file: &Path, prefix: Option<&Path>, rev: Option<&str>) -> Option<String> {
+    let name = prefix.and_then(|prefix| {
+        file.strip_prefix(prefix)
+            .ok()
+            .map(|path| path.to_string_lossy().into_owned())
+    });
+    match rev {
+        Some(rev) => Some(format!(
+            "{}@{rev}",
+            name.unwrap_or_else(|| file.to_string_lossy().into_owned())
+        )),
+        None => name,
+    }
+}
+
+/// Moves the revision of a `file@rev` argument to `rev`, unless a file with that name exists or
+/// `file` does not exist at `rev`
+fn split_revision(subcommand: &mut Command) {
+    let (Command::TreeSitter { file, rev, .. } | Command::Outline { file, rev, .. }) = subcommand
+    else {
+        return;
+    };
+    if rev.is_some() || file.exists() {
+        return;
+    }
+    if let Some((path, revision)) = file.to_str().and_then(|file| file.rsplit_once('@')) {
+        let path = PathBuf::from(path);
+        if git_at_revision(&["cat-file", "-e"], &path, revision)
+            .is_ok_and(|output| output.status.success())
+        {
+            *rev = Some(revision.to_string());
+            *file = path;
+        }
+    }
+}
+
+/// Reads a file to be highlighted with tree-sitter, either from the working tree or from a git
+/// revision, with its tabs expanded if `tab_width` is set
+fn read_code(path: &Path, rev: Option<&str>, config: &Config) -> Result<String> {
+    let code = match (rev, path.to_str().and_then(|path| path.rsplit_once('@'))) {
+        (Some(rev), _) => read_revision(path, rev)?,
+        // `split_revision` did not find the file at the revision
+        (None, Some((file, rev))) if !path.exists() => bail!(
+            "Could not read input file at `{}`, and there is no `{file}` at git revision `{rev}`",
+            path.to_string_lossy()
+        ),
+        (None, _) => read_file(path)?,
+    };
+    Ok(range::expand_tabs(&code, config.tab_width))
+}
+
+/// Reads a file as it was at a git revision from the repository containing it
+fn read_revision(path: &Path, rev: &str) -> Result<String> {
+    let output = git_at_revision(&["cat-file", "blob"], path, rev)?;
+    if !output.status.success() {
+        bail!(
+            "could not read `{}` at revision `{rev}`: {}",
+            path.to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).with_context(|| {
+        format!(
+            "`{}` at revision `{rev}` is not valid UTF-8",
+            path.to_string_lossy()
+        )
+    })
+}
+
+/// Runs a git command with the object of `path` at `rev` as its last argument, in the directory
+/// containing `path`. Revisions starting with `-` are not taken as options.
+fn git_at_revision(args: &[&str], path: &Path, rev: &str) -> Result<process::Output> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("`{}` is not a file", path.to_string_lossy()))?;
+    process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .arg("--end-of-options")
+        .arg(format!("{rev}:./{}", name.to_string_lossy()))
+        .output()
+        .with_context(|| "could not run `git`")
+}
+
 fn read_file(path: &Path) -> Result<String> {
     let raw_code = fs::read_to_string(path)
         .with_context(|| format!("Could not read input file at `{}`", path.to_string_lossy()))?;
@@ -0,0 +1,153 @@
+use std::{collections::BTreeSet, ops::RangeInclusive};
+
+use anyhow::{bail, Context, Result};
+use tree_sitter::{Node, Query, QueryCursor};
+
+use crate::{
+    elide,
+    range::Range,
+    ts::{self, Settings},
+};
+
+/// Captures of the tags query whose bodies are left out of the outline
+const FUNCTION_CAPTURES: &[&str] = &["definition.function", "definition.method"];
+
+/// The ranges of `code` showing the definitions captured by the tags query: all lines of types,
+/// traits and modules, but only the signatures of functions and methods. The first and last line
+/// of the nodes containing a definition, like `impl` blocks, are kept as well, and ranges which
+/// are only separated by blank lines are joined.
+pub fn ranges(settings: &Settings, code: &str) -> Result<Vec<Range>> {
+    if settings.tags_query.is_empty() {
+        bail!(
+            "no `{}` found for this language in the query search dirs",
+            ts::TAGS_FILE_NAME
+        );
+    }
+    let query = Query::new(settings.lang, &settings.tags_query)
+        .with_context(|| format!("invalid `{}`", ts::TAGS_FILE_NAME))?;
+    let tree = ts::parse(settings.lang, code)?;
+
+    let mut rows = BTreeSet::new();
+    let mut bodies = vec![];
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(&query, tree.root_node(), code.as_bytes()) {
+        for capture in query_match.captures {
+            let name = &query.capture_names()[capture.index as usize];
+            if !name.starts_with("definition.") {
+                continue;
+            }
+            let node = capture.node;
+            rows.extend(node.start_position().row..=elide::end_row(node));
+            // the root node is left out, it always starts in the first line
+            let mut parent = node.parent();
+            while let Some(ancestor) = parent.filter(|ancestor| ancestor.parent().is_some()) {
+                rows.insert(ancestor.start_position().row);
+                rows.insert(elide::end_row(ancestor));
+                parent = ancestor.parent();
+            }
+            if FUNCTION_CAPTURES.contains(&name.as_str()) {
+                bodies.extend(body_rows(node));
+            }
+        }
+    }
+    if rows.is_empty() {
+        bail!("the tags query did not find any definitions");
+    }
+
+    // bodies of nested functions are already hidden by the outer ones
+    bodies.sort_by_key(|rows| (*rows.start(), usize::MAX - rows.end()));
+    let mut hidden: Vec<RangeInclusive<usize>> = vec![];
+    for body in bodies {
+        if hidden.last().is_none_or(|last| body.start() > last.end()) {
+            hidden.push(body);
+        }
+    }
+
+    let lines: Vec<_> = code.lines().collect();
+    let mut ranges: Vec<Range> = vec![];
+    for row in rows {
+        match ranges.last_mut() {
+            Some(range)
+                if lines[range.end + 1..row]
+                    .iter()
+                    .all(|line| line.trim().is_empty()) =>
+            {
+                range.end = row
+            }
+            _ => ranges.push(Range {
+                start: row,
+                end: row,
+                ..Range::default()
+            }),
+        }
+    }
+    Ok(elide::split_ranges(&ranges, &hidden, &lines))
+}
+
+/// The rows of the statements in the body of a function, unless they start in the line of its
+/// signature. A closing delimiter in the last of these rows is kept.
+fn body_rows(node: Node) -> Option<RangeInclusive<usize>> {
+    let body = node.child_by_field_name("body")?;
+    let mut cursor = body.walk();
+    let statements: Vec<_> = body.named_children(&mut cursor).collect();
+    let start = statements.first()?.start_position().row;
+    let mut end = elide::end_row(*statements.last()?);
+    if body
+        .child(body.child_count().checked_sub(1)?)
+        .is_some_and(|last| !last.is_named() && last.start_position().row == end)
+    {
+        end = end.checked_sub(1)?;
+    }
+    (start > node.start_position().row && start <= end).then_some(start..=end)
+}
+
+// the tests need a grammar, which is only available without setup when it is bundled
+#[cfg(all(test, feature = "bundled-grammars"))]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// The rows of the ranges of the outline
+    fn outline(file_name: &str, code: &str) -> Vec<(usize, usize)> {
+        let config = serde_json::from_value(serde_json::json!({})).unwrap();
+        let settings = ts::settings_for_file(config, Path::new(file_name)).unwrap();
+        ranges(&settings, code)
+            .unwrap()
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    #[test]
+    fn outlines_rust_with_the_bundled_tags_query() {
+        let code = "\
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    fn norm(&self) -> i32 {
+        let x = self.x;
+        x * x
+    }
+}
+";
+        assert_eq!(outline("file.rs", code), [(0, 5), (8, 9)]);
+    }
+
+    #[test]
+    fn outlines_typescript_with_the_javascript_tags() {
+        let code = "\
+interface Shape {
+    area(): number;
+}
+
+function area(shape: Shape) {
+    const a = shape.area();
+    return a;
+}
+";
+        assert_eq!(outline("file.ts", code), [(0, 4), (7, 7)]);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{fs, iter::Peekable, path::Path, str::Chars};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    bundled,
+    config::Config,
+    theme::{Style, ThemeValue},
+    ts, Cli, Command,
+};
+
+/// Sample code shown for every installed grammar which recognizes one of the file extensions
+const SNIPPETS: &[(&str, &str)] = &[
+    ("rs", include_str!("./samples/sample.rs")),
+    ("c", include_str!("./samples/sample.c")),
+    ("cpp", include_str!("./samples/sample.cpp")),
+    ("py", include_str!("./samples/sample.py")),
+    ("js", include_str!("./samples/sample.js")),
+    ("ts", include_str!("./samples/sample.ts")),
+    ("go", include_str!("./samples/sample.go")),
+    ("java", include_str!("./samples/sample.java")),
+    ("sh", include_str!("./samples/sample.sh")),
+    ("json", include_str!("./samples/sample.json")),
+    ("toml", include_str!("./samples/sample.toml")),
+    ("yaml", include_str!("./samples/sample.yaml")),
+    ("md", include_str!("./samples/sample.md")),
+    ("tex", include_str!("./samples/sample.tex")),
+    ("lua", include_str!("./samples/sample.lua")),
+    ("html", include_str!("./samples/sample.html")),
+    ("css", include_str!("./samples/sample.css")),
+];
+
+pub fn run(config: &Config, cli: &Cli, output_path: &Path) -> Result<()> {
+    // listings in the preview use inline styles so that the document is standalone
+    let mut config = config.clone();
+    config.semantic_styles = false;
+
+    let swatches: Vec<_> = config
+        .theme
+        .iter()
+        .map(|(key, value)| (key.as_str(), value, describe(value)))
+        .collect();
+
+    // bundled grammars take precedence over loader grammars with the same file types
+    let loader = ts::loader(&config)?;
+    let grammars = bundled::GRAMMARS
+        .iter()
+        .map(|grammar| (grammar.name.to_string(), grammar.file_types.to_vec()))
+        .chain(
+            loader
+                .get_all_language_configurations()
+                .into_iter()
+                .map(|(lang_config, _)| {
+                    (
+                        lang_config.scope.as_deref().map_or(String::new(), |scope| {
+                            scope.trim_start_matches("source.").to_string()
+                        }),
+                        lang_config.file_types.iter().map(String::as_str).collect(),
+                    )
+                }),
+        );
+    let mut listings = vec![];
+    let mut previewed_exts = vec![];
+    for (name, file_types) in grammars {
+        let Some((ext, code)) = SNIPPETS
+            .iter()
+            .find(|(ext, _)| file_types.contains(ext) && !previewed_exts.contains(ext))
+        else {
+            continue;
+        };
+        previewed_exts.push(*ext);
+        let name = match name.is_empty() {
+            true => ext.to_string(),
+            false => name,
+        };
+        let file = format!("sample.{ext}");
+        let sample_cli = Cli {
+            fancyvrb_args: cli.fancyvrb_args.clone(),
+            theme: cli.theme.clone(),
+            print_mode: cli.print_mode,
+            config: cli.config.clone(),
+            default_config: cli.default_config,
+            strict: false,
+            subcommand: Command::TreeSitter {
+                file: file.clone().into(),
+                raw: false,
+                raw_queries: false,
+                ranges: vec![],
+                elide: vec![],
+                filename_strip_prefix: None,
+                rev: None,
+                show_rev: false,
+            },
+        };
+        let listing =
+            ts::get_settings(config.clone(), &sample_cli.subcommand).and_then(|settings| {
+                ts::highlight(
+                    code.trim_end(),
+                    None,
+                    None,
+                    &sample_cli,
+                    settings,
+                    Some(file),
+                )
+            });
+        match listing {
+            Ok(listing) => listings.push((name, listing)),
+            Err(err) => eprintln!("lirstings: skipping preview for `{name}`: {err:#}"),
+        }
+    }
+
+    let document = match output_path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => html_document(&swatches, &listings),
+        _ => tex_document(&swatches, &listings),
+    };
+    fs::write(output_path, document).with_context(|| {
+        format!(
+            "could not write preview to `{}`",
+            output_path.to_string_lossy()
+        )
+    })?;
+    eprintln!(
+        "lirstings: preview written to `{}`",
+        output_path.to_string_lossy()
+    );
+    Ok(())
+}
+
+fn describe(value: &ThemeValue) -> String {
+    let style = match value {
+        ThemeValue::Color(color) => return color.clone(),
+        ThemeValue::Object(style) => style,
+    };
+    let Style {
+        color,
+        background,
+        underline_style,
+        underline_color,
+        nocolor,
+        ..
+    } = style;
+    let mut parts = vec![];
+    parts.extend(color.clone());
+    parts.extend(background.as_ref().map(|color| format!("on {color}")));
+    for (enabled, name) in [
+        (style.bold, "bold"),
+        (style.italic, "italic"),
+        (style.smallcaps, "smallcaps"),
+        (style.strikethrough, "strikethrough"),
+        (style.overline, "overline"),
+        (*nocolor, "nocolor"),
+    ] {
+        if enabled {
+            parts.push(name.to_string());
+        }
+    }
+    if style.underline || underline_style.is_some() || underline_color.is_some() {
+        let mut underline = String::from("underline");
+        if let Some(underline_style) = underline_style {
+            underline += &format!(" {underline_style:?}").to_lowercase();
+        }
+        if let Some(underline_color) = underline_color {
+            underline += &format!(" {underline_color}");
+        }
+        parts.push(underline);
+    }
+    parts.join(", ")
+}
+
+fn tex_document(swatches: &[(&str, &ThemeValue, String)], listings: &[(String, String)]) -> String {
+    let mut out = String::from("% compile with `lualatex`\n\\documentclass{report}\n");
+    out += include_str!("./lirstings.tex");
+    out += "\\usepackage{longtable}\n\n\\begin{document}\n\\section*{Theme}\n";
+    out += "\\begin{longtable}{lll}\n\\textbf{Key} & \\textbf{Sample} & \\textbf{Style} \\\\\n\\hline\n\\endhead\n";
+    for (key, value, description) in swatches {
+        out += &format!(
+            "\\texttt{{{}}} & \\texttt{{{}}} & \\texttt{{{}}} \\\\\n",
+            tex_escape(key),
+            value.write(&tex_escape(key)).replace('×', "\\"),
+            tex_escape(description)
+        );
+    }
+    out += "\\end{longtable}\n";
+    for (name, listing) in listings {
+        out += &format!("\n\\section*{{{}}}\n{listing}\n", tex_escape(name));
+    }
+    out + "\\end{document}\n"
+}
+
+fn tex_escape(text: &str) -> String {
+    text.replace('_', "\\_").replace('#', "\\#")
+}
+
+fn html_document(
+    swatches: &[(&str, &ThemeValue, String)],
+    listings: &[(String, String)],
+) -> String {
+    let mut out = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>lirstings theme preview</title>\n",
+        "<style>\n",
+        "body { font-family: sans-serif; max-width: 60em; margin: auto; }\n",
+        "td, pre { font-family: monospace; }\n",
+        "td { padding: 0.1em 1em; }\n",
+        ".ln { display: inline-block; width: 2.5em; color: #a0a1a7; user-select: none; }\n",
+        ".sc { font-variant: small-caps; }\n",
+        "</style>\n</head>\n<body>\n<h1>Theme</h1>\n<table>\n",
+        "<tr><th>Key</th><th>Sample</th><th>Style</th></tr>\n",
+    ));
+    for (key, value, description) in swatches {
+        out += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(key),
+            verbatim_to_html(&value.write(key)),
+            html_escape(description)
+        );
+    }
+    out += "</table>\n";
+    for (name, listing) in listings {
+        let body = listing
+            .split_once('\n')
+            .map_or("", |(_, rest)| rest)
+            .trim_end_matches("\\end{Verbatim}")
+            .trim_end_matches('\n');
+        out += &format!(
+            "<h2>{}</h2>\n<pre>{}</pre>\n",
+            html_escape(name),
+            verbatim_to_html(body)
+        );
+    }
+    out + "</body>\n</html>\n"
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Converts the commands emitted inside `Verbatim` environments by lirstings to HTML
+fn verbatim_to_html(verbatim: &str) -> String {
+    let mut out = String::new();
+    let mut closing_tags = vec![];
+    let mut chars = verbatim.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '×' if matches!(chars.peek(), Some('{' | '}')) => {
+                out += &html_escape(&chars.next().expect("peeked above").to_string())
+            }
+            '×' => {
+                let command: String = iter_while(&mut chars, char::is_alphabetic);
+                let open = match command.as_str() {
+                    "setcounter" => {
+                        group(&mut chars);
+                        match group(&mut chars).as_str() {
+                            "0" => "<span class=\"ln\"></span>".to_string(),
+                            number => format!("<span class=\"ln\">{number}</span>"),
+                        }
+                    }
+                    "textcolor" => format!("<span style=\"color: {}\">", color(&mut chars)),
+                    "colorbox" => {
+                        format!("<span style=\"background: {}\">", color(&mut chars))
+                    }
+                    "LirstUnderline" => {
+                        let line_style = match group(&mut chars).trim_start_matches('×') {
+                            "uuline" => "double",
+                            "uwave" => "wavy",
+                            "dotuline" => "dotted",
+                            "dashuline" => "dashed",
+                            _ => "solid",
+                        };
+                        let color = color(&mut group(&mut chars).chars().peekable());
+                        format!("<span style=\"text-decoration: underline {line_style} {color}\">")
+                    }
+                    "uline" => "<u>".to_string(),
+                    "uuline" => "<u style=\"text-decoration-style: double\">".to_string(),
+                    "uwave" => "<u style=\"text-decoration-style: wavy\">".to_string(),
+                    "dotuline" => "<u style=\"text-decoration-style: dotted\">".to_string(),
+                    "dashuline" => "<u style=\"text-decoration-style: dashed\">".to_string(),
+                    "sout" => "<s>".to_string(),
+                    "LirstOverline" => "<span style=\"text-decoration: overline\">".to_string(),
+                    "textit" => "<i>".to_string(),
+                    "textbf" => "<b>".to_string(),
+                    "textsc" => "<span class=\"sc\">".to_string(),
+                    _ => String::new(),
+                };
+                out += &open;
+                if chars.peek() == Some(&'{') {
+                    chars.next();
+                    closing_tags.push(match open.split_once([' ', '>']) {
+                        Some((tag, _)) => format!("</{}>", &tag[1..]),
+                        None => String::new(),
+                    });
+                }
+            }
+            '{' => closing_tags.push(String::new()),
+            '}' => out += &closing_tags.pop().unwrap_or_default(),
+            _ => out += &html_escape(&char.to_string()),
+        }
+    }
+    out
+}
+
+fn iter_while(chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) -> String {
+    let mut out = String::new();
+    while let Some(char) = chars.next_if(|char| predicate(*char)) {
+        out.push(char);
+    }
+    out
+}
+
+/// Reads a brace delimited argument
+fn group(chars: &mut Peekable<Chars>) -> String {
+    if chars.next_if_eq(&'{').is_none() {
+        return String::new();
+    }
+    let mut depth = 1;
+    let mut out = String::new();
+    for char in chars.by_ref() {
+        match char {
+            '{' => depth += 1,
+            '}' if depth == 1 => break,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        out.push(char);
+    }
+    out
+}
+
+/// Reads a `[HTML]{rrggbb}` color argument
+fn color(chars: &mut Peekable<Chars>) -> String {
+    if chars.next_if_eq(&'[').is_some() {
+        iter_while(chars, |char| char != ']');
+        chars.next();
+    }
+    format!("#{}", group(chars))
+}
@@ -0,0 +1,510 @@
+use std::cmp::Reverse;
+
+use anyhow::{bail, Result};
+use tree_sitter::{Query, QueryMatch, QueryPredicateArg};
+
+use crate::lua_pattern;
+
+/// Predicates which neither tree-sitter nor tree-sitter-highlight evaluate, checked by
+/// `satisfies_runtime_predicates` instead
+pub const RUNTIME_PREDICATES: [&str; 4] = [
+    "has-ancestor?",
+    "not-has-ancestor?",
+    "has-parent?",
+    "not-has-parent?",
+];
+
+/// Neovim's default pattern priority, set with `(#set! priority n)`
+const DEFAULT_PRIORITY: i64 = 100;
+
+/// An S-expression of a query, with comments and formatting removed
+#[derive(Clone)]
+enum Node {
+    /// A parenthesized group or a `[...]` alternation
+    Group { open: char, children: Vec<Node> },
+    /// Node names, fields, captures, anchors, quantifiers, predicate names and so on
+    Atom(String),
+    /// A string literal with its escape sequences decoded
+    Str(String),
+}
+
+impl Node {
+    fn write(&self, out: &mut String) {
+        match self {
+            Node::Group { open, children } => {
+                out.push(*open);
+                for (index, child) in children.iter().enumerate() {
+                    if index > 0 {
+                        out.push(' ');
+                    }
+                    child.write(out);
+                }
+                out.push(if *open == '(' { ')' } else { ']' });
+            }
+            Node::Atom(atom) => out.push_str(atom),
+            Node::Str(str) => {
+                out.push('"');
+                for char in str.chars() {
+                    match char {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str(r"\\"),
+                        '\n' => out.push_str(r"\n"),
+                        '\r' => out.push_str(r"\r"),
+                        '\t' => out.push_str(r"\t"),
+                        '\0' => out.push_str(r"\0"),
+                        char => out.push(char),
+                    }
+                }
+                out.push('"');
+            }
+        }
+    }
+
+    /// The string value of a predicate argument, which may be written without quotes
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Node::Str(str) => Some(str),
+            Node::Atom(atom) if !atom.starts_with('@') => Some(atom),
+            _ => None,
+        }
+    }
+}
+
+/// Rewrites Neovim queries into queries tree-sitter 0.20 understands. Predicates Neovim adds are
+/// translated to `#match?` and `#not-match?` where possible, patterns with Lua patterns that have
+/// no regex equivalent are dropped with a warning, directives only meaningful to an editor are
+/// removed, and patterns are reordered because Neovim lets later and higher priority
+/// patterns win while tree-sitter-highlight uses the first matching one.
+pub fn process(source: &str) -> Result<String> {
+    let mut patterns: Vec<(i64, Vec<Node>)> = vec![];
+    for node in parse(source)? {
+        let is_suffix = matches!(&node, Node::Atom(atom)
+            if atom.starts_with('@') || ["*", "+", "?"].contains(&atom.as_str()));
+        match patterns.last_mut() {
+            Some((_, pattern)) if is_suffix => pattern.push(node),
+            _ => patterns.push((DEFAULT_PRIORITY, vec![node])),
+        }
+    }
+
+    let mut rewritten = vec![];
+    for (mut priority, mut pattern) in patterns {
+        let mut untranslatable = None;
+        for node in &mut pattern {
+            rewrite(node, &mut priority, &mut untranslatable)?;
+        }
+        match untranslatable {
+            Some(reason) => eprintln!("lirstings: dropping a query pattern, {reason}"),
+            None => rewritten.push((priority, pattern)),
+        }
+    }
+    let mut patterns = rewritten;
+    patterns.reverse();
+    patterns.sort_by_key(|(priority, _)| Reverse(*priority));
+
+    let mut out = String::new();
+    for (_, pattern) in patterns {
+        for (index, node) in pattern.iter().enumerate() {
+            if index > 0 {
+                out.push(' ');
+            }
+            node.write(&mut out);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn parse(source: &str) -> Result<Vec<Node>> {
+    // the top level and every unclosed group with its opening line
+    let mut stack: Vec<(char, usize, Vec<Node>)> = vec![(' ', 0, vec![])];
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '\n' => line += 1,
+            char if char.is_whitespace() => {}
+            ';' => {
+                for char in chars.by_ref() {
+                    if char == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '(' | '[' => stack.push((char, line, vec![])),
+            ')' | ']' => {
+                let expected = if char == ')' { '(' } else { '[' };
+                match stack.pop() {
+                    Some((open, _, children)) if open == expected => stack
+                        .last_mut()
+                        .expect("the top level is never popped")
+                        .2
+                        .push(Node::Group { open, children }),
+                    _ => bail!("unexpected `{char}` in line {line}"),
+                }
+            }
+            '"' => {
+                let start_line = line;
+                let mut str = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => str.push('\n'),
+                            Some('r') => str.push('\r'),
+                            Some('t') => str.push('\t'),
+                            Some('0') => str.push('\0'),
+                            Some(char) => str.push(char),
+                            None => bail!("unterminated string in line {start_line}"),
+                        },
+                        Some(char) => {
+                            if char == '\n' {
+                                line += 1;
+                            }
+                            str.push(char);
+                        }
+                        None => bail!("unterminated string in line {start_line}"),
+                    }
+                }
+                stack
+                    .last_mut()
+                    .expect("checked above")
+                    .2
+                    .push(Node::Str(str));
+            }
+            char => {
+                let mut atom = char.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()[]\";".contains(next) {
+                        break;
+                    }
+                    atom.push(next);
+                    chars.next();
+                }
+                stack
+                    .last_mut()
+                    .expect("checked above")
+                    .2
+                    .push(Node::Atom(atom));
+            }
+        }
+    }
+    match stack.pop() {
+        Some((open, line, _)) if !stack.is_empty() => bail!("unclosed `{open}` from line {line}"),
+        Some((_, _, nodes)) => Ok(nodes),
+        None => unreachable!("the top level is never popped"),
+    }
+}
+
+/// Rewrites all predicates and directives in a node, removing those that have no effect outside
+/// of Neovim and recording the pattern's priority
+/// Rewrites the predicates in `node`. If one of them cannot be translated, the reason is stored
+/// in `untranslatable` and the pattern should be dropped.
+fn rewrite(node: &mut Node, priority: &mut i64, untranslatable: &mut Option<String>) -> Result<()> {
+    let Node::Group { children, .. } = node else {
+        return Ok(());
+    };
+    let mut index = 0;
+    while index < children.len() {
+        let predicate = match &children[index] {
+            Node::Group {
+                open: '(',
+                children,
+            } => match children.first() {
+                Some(Node::Atom(name)) if name.starts_with('#') => Some(children),
+                _ => None,
+            },
+            _ => None,
+        };
+        match predicate {
+            Some(predicate) => match rewrite_predicate(predicate, priority, untranslatable)? {
+                Some(replacement) => {
+                    children[index] = replacement;
+                    index += 1;
+                }
+                None => {
+                    children.remove(index);
+                }
+            },
+            None => {
+                rewrite(&mut children[index], priority, untranslatable)?;
+                index += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rewrite_predicate(
+    predicate: &[Node],
+    priority: &mut i64,
+    untranslatable: &mut Option<String>,
+) -> Result<Option<Node>> {
+    let Some(Node::Atom(name)) = predicate.first() else {
+        unreachable!("predicates start with their name");
+    };
+    let operator = &name[1..];
+    let args = &predicate[1..];
+    let capture_and_strings = || -> Result<(&str, Vec<&str>)> {
+        let (Some(Node::Atom(capture)), Some(strings)) = (
+            args.first(),
+            args[1..]
+                .iter()
+                .map(Node::as_str)
+                .collect::<Option<Vec<_>>>(),
+        ) else {
+            bail!("`{name}` expects a capture followed by strings");
+        };
+        if !capture.starts_with('@') || strings.is_empty() {
+            bail!("`{name}` expects a capture followed by strings");
+        }
+        Ok((capture, strings))
+    };
+    let match_predicate = |negated: bool, capture: &str, regex: String| Node::Group {
+        open: '(',
+        children: vec![
+            Node::Atom(if negated { "#not-match?" } else { "#match?" }.to_string()),
+            Node::Atom(capture.to_string()),
+            Node::Str(regex),
+        ],
+    };
+    let escaped_alternatives = |strings: &[&str]| {
+        strings
+            .iter()
+            .map(|str| regex::escape(str))
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+
+    Ok(match operator {
+        "lua-match?" | "not-lua-match?" | "vim-match?" | "not-vim-match?" => {
+            let (capture, strings) = capture_and_strings()?;
+            let [pattern] = strings[..] else {
+                bail!("`{name}` expects a capture and a single pattern");
+            };
+            let regex = match operator.ends_with("lua-match?") {
+                true => match lua_pattern::to_regex(pattern) {
+                    Ok(regex) => regex,
+                    Err(err) => {
+                        *untranslatable = Some(format!(
+                            "could not translate `{name}` pattern `{pattern}`: {err:#}"
+                        ));
+                        return Ok(None);
+                    }
+                },
+                false => vim_regex(pattern),
+            };
+            Some(match_predicate(
+                operator.starts_with("not-"),
+                capture,
+                regex,
+            ))
+        }
+        "any-of?" | "not-any-of?" => {
+            let (capture, strings) = capture_and_strings()?;
+            let regex = format!("^(?:{})$", escaped_alternatives(&strings));
+            Some(match_predicate(operator == "not-any-of?", capture, regex))
+        }
+        "contains?" | "not-contains?" => {
+            let (capture, strings) = capture_and_strings()?;
+            let regex = escaped_alternatives(&strings);
+            Some(match_predicate(operator == "not-contains?", capture, regex))
+        }
+        // ranges and text of captures can only be changed for injections in Neovim
+        "offset!" | "gsub!" | "trim!" | "make-range!" => None,
+        "set!" => {
+            let values: Vec<_> = args
+                .iter()
+                .skip_while(|arg| matches!(arg, Node::Atom(atom) if atom.starts_with('@')))
+                .filter_map(Node::as_str)
+                .collect();
+            match values[..] {
+                ["priority", value] => {
+                    *priority = value
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("invalid priority `{value}`"))?;
+                    None
+                }
+                // concealing hides text in the editor, listings always show the source
+                ["conceal", ..] => None,
+                _ => Some(Node::Group {
+                    open: '(',
+                    children: predicate.to_vec(),
+                }),
+            }
+        }
+        _ => Some(Node::Group {
+            open: '(',
+            children: predicate.to_vec(),
+        }),
+    })
+}
+
+/// Translates a Vim regex, which may switch between magic (`\m`) and very magic (`\v`) mode
+fn vim_regex(pattern: &str) -> String {
+    let mut very_magic = false;
+    let mut out = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some('v') => very_magic = true,
+                Some('m') => very_magic = false,
+                Some('<' | '>') => out.push_str(r"\b"),
+                Some('%') if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    out.push_str("(?:");
+                }
+                Some('=') if !very_magic => out.push('?'),
+                Some(char @ ('(' | ')' | '|' | '+' | '?' | '{' | '}')) if !very_magic => {
+                    out.push(char)
+                }
+                Some('a') => out.push_str("[a-zA-Z]"),
+                Some('h') => out.push_str("[a-zA-Z_]"),
+                Some('l') => out.push_str("[a-z]"),
+                Some('u') => out.push_str("[A-Z]"),
+                Some('x') => out.push_str("[0-9a-fA-F]"),
+                Some(char) if char.is_ascii_alphanumeric() => {
+                    out.push('\\');
+                    out.push(char);
+                }
+                Some(char) => out.push_str(&regex::escape(&char.to_string())),
+                None => out.push_str(r"\\"),
+            },
+            '%' if very_magic && chars.peek() == Some(&'(') => {
+                chars.next();
+                out.push_str("(?:");
+            }
+            '=' if very_magic => out.push('?'),
+            '<' | '>' if very_magic => out.push_str(r"\b"),
+            '(' | ')' | '|' | '+' | '?' | '{' | '}' if !very_magic => {
+                out.push_str(&regex::escape(&char.to_string()))
+            }
+            char => out.push(char),
+        }
+    }
+    out
+}
+
+/// Checks the predicates listed in `RUNTIME_PREDICATES` for a match, ignoring all others
+pub fn satisfies_runtime_predicates(query: &Query, query_match: &QueryMatch) -> bool {
+    query
+        .general_predicates(query_match.pattern_index)
+        .iter()
+        .filter(|predicate| RUNTIME_PREDICATES.contains(&predicate.operator.as_ref()))
+        .all(|predicate| {
+            let (Some(QueryPredicateArg::Capture(capture)), types) =
+                (predicate.args.first(), &predicate.args[1..])
+            else {
+                return true;
+            };
+            let is_listed = |kind: &str| {
+                types.iter().any(
+                    |arg| matches!(arg, QueryPredicateArg::String(str) if str.as_ref() == kind),
+                )
+            };
+            let negated = predicate.operator.starts_with("not-");
+            query_match
+                .captures
+                .iter()
+                .filter(|c| c.index == *capture)
+                .all(|c| {
+                    let found = match predicate.operator.ends_with("has-parent?") {
+                        true => c
+                            .node
+                            .parent()
+                            .is_some_and(|parent| is_listed(parent.kind())),
+                        false => {
+                            let mut ancestor = c.node.parent();
+                            let mut found = false;
+                            while let Some(node) = ancestor {
+                                if is_listed(node.kind()) {
+                                    found = true;
+                                    break;
+                                }
+                                ancestor = node.parent();
+                            }
+                            found
+                        }
+                    };
+                    found != negated
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverses_patterns_and_drops_comments() {
+        let source = "; keywords\n(a) @x\n\n(b) @y ; trailing\n";
+        assert_eq!(process(source).unwrap(), "(b) @y\n(a) @x\n");
+    }
+
+    #[test]
+    fn orders_by_priority() {
+        let source = "((a) @x (#set! priority 105))\n(b) @y\n((c) @z (#set! \"priority\" \"90\"))";
+        assert_eq!(process(source).unwrap(), "((a) @x)\n(b) @y\n((c) @z)\n");
+        assert!(process("((a) @x (#set! priority high))").is_err());
+    }
+
+    #[test]
+    fn translates_neovim_predicates() {
+        assert_eq!(
+            process(r#"((identifier) @constant (#lua-match? @constant "^%u"))"#).unwrap(),
+            "((identifier) @constant (#match? @constant \"(?s)^[A-Z]\"))\n"
+        );
+        assert_eq!(
+            process(r#"((identifier) @x (#any-of? @x "self" "super"))"#).unwrap(),
+            "((identifier) @x (#match? @x \"^(?:self|super)$\"))\n"
+        );
+        assert_eq!(
+            process(r#"((comment) @x (#not-contains? @x "a.b"))"#).unwrap(),
+            "((comment) @x (#not-match? @x \"a\\\\.b\"))\n"
+        );
+        assert!(process(r#"((string) @x (#any-of? x "a"))"#).is_err());
+    }
+
+    #[test]
+    fn drops_patterns_with_untranslatable_lua_patterns() {
+        assert_eq!(
+            process(
+                r#"
+                ((string) @x (#lua-match? @x "%b()"))
+                (comment) @comment
+                "#
+            )
+            .unwrap(),
+            "(comment) @comment\n"
+        );
+    }
+
+    #[test]
+    fn removes_editor_directives() {
+        assert_eq!(
+            process("((comment) @c (#offset! @c 0 2 0 0) (#set! conceal \"\"))").unwrap(),
+            "((comment) @c)\n"
+        );
+        assert_eq!(
+            process("((a) @x (#set! injection.language \"rust\"))").unwrap(),
+            "((a) @x (#set! injection.language \"rust\"))\n"
+        );
+    }
+
+    #[test]
+    fn keeps_tree_sitter_predicates_and_suffixes() {
+        let source = "[(a) (b)] @x\n((c) @y (#eq? @y \"c\"))";
+        assert_eq!(
+            process(source).unwrap(),
+            "((c) @y (#eq? @y \"c\"))\n[(a) (b)] @x\n"
+        );
+    }
+
+    #[test]
+    fn reports_unbalanced_groups() {
+        assert!(process("((a) @x").is_err());
+        assert!(process("(a)) @x").is_err());
+        assert!(process("((a) @x (#eq? @x \"a))").is_err());
+    }
+}
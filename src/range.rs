@@ -1,7 +1,9 @@
-use std::{ops::Add, str::FromStr};
+use std::{cmp::Ordering, fmt, ops::Add, str::FromStr};
 
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context, Result};
 use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
 
 #[derive(Debug, Clone, Copy, Hash, Default)]
 pub struct Range {
@@ -13,75 +15,428 @@ pub struct Range {
     pub end_col: Option<usize>,
 }
 
-impl FromStr for Range {
+/// A line of a range literal, resolved once the file is read
+#[derive(Debug, Clone, Hash)]
+pub enum Line {
+    /// 0-based index from the first line
+    FromStart(usize),
+    /// 0-based index from the last line, so `$` is `FromEnd(0)`
+    FromEnd(usize),
+    /// The line of the `occurrence`-th match of `pattern`, moved by `offset` lines
+    Anchor {
+        pattern: Pattern,
+        occurrence: usize,
+        offset: isize,
+    },
+}
+
+/// What an anchor looks for in each line
+#[derive(Debug, Clone, Hash)]
+pub enum Pattern {
+    Regex(String),
+    Literal(String),
+}
+
+impl Line {
+    /// The index of the line in `lines`, anchors only match at `from` or later
+    fn resolve(&self, lines: &[&str], from: usize) -> Result<usize> {
+        let line_count = lines.len();
+        match self {
+            Line::FromStart(index) if *index < line_count => Ok(*index),
+            Line::FromEnd(index) if *index < line_count => Ok(line_count - 1 - index),
+            Line::FromStart(_) | Line::FromEnd(_) => {
+                bail!("{self} is out of bounds, the file has {line_count} lines")
+            }
+            Line::Anchor {
+                pattern,
+                occurrence,
+                offset,
+            } => {
+                let regex = match pattern {
+                    Pattern::Regex(regex) => Regex::new(regex)?,
+                    Pattern::Literal(text) => Regex::new(&regex::escape(text))?,
+                };
+                let (index, _) = lines
+                    .iter()
+                    .enumerate()
+                    .skip(from)
+                    .filter(|(_, line)| regex.is_match(line))
+                    .nth(occurrence - 1)
+                    .with_context(|| match from {
+                        0 => format!("{self} does not match any line"),
+                        _ => format!("{self} does not match line {} or later", from + 1),
+                    })?;
+                index
+                    .checked_add_signed(*offset)
+                    .filter(|index| *index < line_count)
+                    .with_context(|| {
+                        format!(
+                            "{self} refers to line {}, but the file has {line_count} lines",
+                            index as isize + offset + 1
+                        )
+                    })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::FromStart(index) => write!(f, "line {}", index + 1),
+            Line::FromEnd(0) => write!(f, "line `$`"),
+            Line::FromEnd(index) => write!(f, "line `$-{index}`"),
+            Line::Anchor {
+                pattern,
+                occurrence,
+                offset,
+            } => {
+                match pattern {
+                    Pattern::Regex(regex) => write!(f, "anchor `/{regex}/")?,
+                    Pattern::Literal(text) => write!(f, "anchor `\"{text}\"")?,
+                }
+                if *occurrence != 1 {
+                    write!(f, "#{occurrence}")?;
+                }
+                match offset {
+                    0 => write!(f, "`"),
+                    _ => write!(f, "{offset:+}`"),
+                }
+            }
+        }
+    }
+}
+
+/// Where a range ends
+#[derive(Debug, Clone, Hash)]
+pub enum End {
+    /// The range is a single line
+    Start,
+    /// The range goes up to the last line
+    Last,
+    Line(Line),
+}
+
+/// A range literal like `5-10`, `3:4-7:12`, `12-` (up to the last line), `-5-` (the last five
+/// lines), `$-10-$` or `42` (a single line). A leading `+n ` or `-n ` changes the indent of the
+/// preceding placeholder, a leading `_` joins the first line onto the previous range instead.
+///
+/// Instead of a number, a line can be given as the first line matching a regex (`/^fn main/`) or
+/// containing some text (`"fn main"`), optionally followed by the occurrence to use (`#2`) and a
+/// line offset (`+1` or `-1`). Ends are searched from the start of the range on, so
+/// `/^fn main/../^}/` selects the whole function. Because `-` after an anchor is an offset, `..`
+/// can be used instead of `-` to separate start and end.
+#[derive(Debug, Clone, Hash)]
+pub struct RangeSpec {
+    pub inline: bool,
+    pub indent_offset: Offset,
+    pub start: Line,
+    pub end: End,
+    pub start_col: Option<usize>,
+    pub end_col: Option<usize>,
+}
+
+impl FromStr for RangeSpec {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let regex =
-            Regex::from_str(r"^ *([+-]\d+|_)? *(\d+)(?::(\d+))? *- *(\d+)(?::(\d+))? *$").unwrap();
-        let groups = regex
-            .captures(s)
-            .with_context(|| "unable to parse range literal")?;
-
-        let inline = groups
-            .get(1)
-            .map_or(false, |capture| capture.as_str() == "_");
-        let indent_offset = match groups.get(1).map(|capture| capture.as_str()) {
-            None | Some("_") => Offset::None,
-            Some(num) if num.starts_with('+') => Offset::Positive(
-                num[1..]
-                    .parse::<usize>()
-                    .with_context(|| "failed to parse indent offset")?,
-            ),
-            Some(num) => Offset::Negative(
-                num[1..]
-                    .parse::<usize>()
-                    .with_context(|| "failed to parse indent offset")?,
-            ),
+        let mut cursor = Cursor { rest: s.trim() };
+        let mut inline = false;
+        let mut indent_offset = Offset::None;
+        if cursor.eat("_") {
+            inline = true;
+        } else if let Some(num) = cursor.signed('+') {
+            indent_offset = Offset::Positive(num?);
+        } else if cursor.rest.starts_with('-') {
+            // `-n ` is an indent offset, while `-n` without a space is a line
+            let mut lookahead = Cursor { rest: cursor.rest };
+            if let Some(num) = lookahead.signed('-') {
+                if lookahead.rest.starts_with(' ') {
+                    indent_offset = Offset::Negative(num?);
+                    cursor = lookahead;
+                }
+            }
+        }
+        cursor.skip_spaces();
+
+        let start = cursor
+            .line()
+            .with_context(|| "failed to parse range start literal")?;
+        let start_col = cursor
+            .column()
+            .with_context(|| "failed to parse range start column literal")?;
+        cursor.skip_spaces();
+        let (end, end_col) = match cursor.eat("..") || cursor.eat("-") {
+            false => (End::Start, None),
+            true => {
+                cursor.skip_spaces();
+                match cursor.rest.is_empty() {
+                    true => (End::Last, None),
+                    false => (
+                        End::Line(
+                            cursor
+                                .line()
+                                .with_context(|| "failed to parse range end literal")?,
+                        ),
+                        cursor
+                            .column()
+                            .with_context(|| "failed to parse range end column literal")?,
+                    ),
+                }
+            }
         };
-        let start = groups[2]
-            .parse::<usize>()
-            .with_context(|| "failed to parse range start literal")?
-            .checked_sub(1)
-            .with_context(|| "line number 0 does not exist")?;
-        let end = groups[4]
-            .parse::<usize>()
-            .with_context(|| "failed to parse range end literal")?
-            .checked_sub(1)
-            .with_context(|| "line number 0 does not exist")?;
-        let start_col = match groups.get(3) {
-            Some(grp) => Some(
-                grp.as_str()
-                    .parse::<usize>()
-                    .with_context(|| "failed to parse range start column literal")?,
-            ),
-            None => None,
+        cursor.skip_spaces();
+        if !cursor.rest.is_empty() {
+            bail!(
+                "unable to parse range literal, unexpected `{}`",
+                cursor.rest
+            );
+        }
+        Ok(Self {
+            inline,
+            indent_offset,
+            start,
+            end,
+            start_col,
+            end_col,
+        })
+    }
+}
+
+/// The remaining input of a range literal
+struct Cursor<'src> {
+    rest: &'src str,
+}
+
+impl Cursor<'_> {
+    fn eat(&mut self, prefix: &str) -> bool {
+        match self.rest.strip_prefix(prefix) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn number(&mut self) -> Option<Result<usize>> {
+        let len = self
+            .rest
+            .find(|char: char| !char.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        if len == 0 {
+            return None;
+        }
+        let (num, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Some(
+            num.parse()
+                .with_context(|| format!("failed to parse number `{num}`")),
+        )
+    }
+
+    /// A number directly following `sign`
+    fn signed(&mut self, sign: char) -> Option<Result<usize>> {
+        let rest = self.rest.strip_prefix(sign)?;
+        if !rest.starts_with(|char: char| char.is_ascii_digit()) {
+            return None;
+        }
+        self.rest = rest;
+        self.number()
+    }
+
+    /// The text up to the next unescaped `delimiter`, which is consumed as well
+    fn delimited(&mut self, delimiter: char) -> Result<String> {
+        let mut out = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((index, char)) = chars.next() {
+            match char {
+                '\\' => match chars.next() {
+                    Some((_, next)) if next == delimiter => out.push(next),
+                    Some((_, next)) => {
+                        out.push('\\');
+                        out.push(next);
+                    }
+                    None => break,
+                },
+                char if char == delimiter => {
+                    self.rest = &self.rest[index + char.len_utf8()..];
+                    return Ok(out);
+                }
+                char => out.push(char),
+            }
+        }
+        bail!("missing closing `{delimiter}`")
+    }
+
+    fn line(&mut self) -> Result<Line> {
+        if self.eat("$") {
+            return Ok(Line::FromEnd(self.signed('-').transpose()?.unwrap_or(0)));
+        }
+        if let Some(num) = self.signed('-') {
+            return Ok(Line::FromEnd(
+                num?.checked_sub(1)
+                    .with_context(|| "line number -0 does not exist")?,
+            ));
+        }
+        let pattern = if self.eat("/") {
+            let regex = self.delimited('/')?;
+            Regex::new(&regex).with_context(|| format!("invalid anchor regex `{regex}`"))?;
+            Pattern::Regex(regex)
+        } else if self.eat("\"") {
+            Pattern::Literal(self.delimited('"')?)
+        } else {
+            return match self.number() {
+                Some(num) => Ok(Line::FromStart(
+                    num?.checked_sub(1)
+                        .with_context(|| "line number 0 does not exist")?,
+                )),
+                None => bail!("expected a line number, `$`, `/regex/` or `\"text\"`"),
+            };
+        };
+        let occurrence = match self.eat("#") {
+            true => self
+                .number()
+                .with_context(|| "missing occurrence after `#`")??,
+            false => 1,
         };
-        let end_col = match groups.get(5) {
-            Some(grp) => Some(
-                grp.as_str()
-                    .parse::<usize>()
-                    .with_context(|| "failed to parse range end column literal")?,
-            ),
-            None => None,
+        if occurrence == 0 {
+            bail!("occurrences start at 1");
+        }
+        let offset = if let Some(num) = self.signed('+') {
+            num? as isize
+        } else if let Some(num) = self.signed('-') {
+            -(num? as isize)
+        } else {
+            0
+        };
+        Ok(Line::Anchor {
+            pattern,
+            occurrence,
+            offset,
+        })
+    }
+
+    fn column(&mut self) -> Result<Option<usize>> {
+        match self.eat(":") {
+            true => Ok(Some(self.number().with_context(|| "missing column")??)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl RangeSpec {
+    pub fn resolve(&self, lines: &[&str]) -> Result<Range> {
+        let start = self.start.resolve(lines, 0)?;
+        let end = match &self.end {
+            End::Start => start,
+            End::Last => lines.len().saturating_sub(1),
+            End::Line(end) => end.resolve(lines, start)?,
         };
         if start > end
             || (start == end
-                && matches!((start_col, end_col), (Some(start), Some(end)) if start > end))
+                && matches!((self.start_col, self.end_col), (Some(start), Some(end)) if start > end))
         {
             bail!("range start is higher than range end");
         }
-        Ok(Self {
-            inline,
-            indent_offset,
+        Ok(Range {
+            inline: self.inline,
+            indent_offset: self.indent_offset,
             start,
             end,
-            start_col,
-            end_col,
+            start_col: self.start_col,
+            end_col: self.end_col,
         })
     }
 }
 
+/// A comma separated list of range literals, commas inside of anchors do not separate ranges
+#[derive(Debug, Clone, Hash)]
+pub struct Ranges(pub Vec<RangeSpec>);
+
+impl FromStr for Ranges {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // clap only shows the outermost error, so the causes are joined into it
+        let parse = |literal: &str| {
+            literal
+                .parse::<RangeSpec>()
+                .map_err(|err| anyhow!("{err:#}"))
+        };
+        let mut specs = vec![];
+        let mut start = 0;
+        let mut delimiter = None;
+        let mut escaped = false;
+        for (index, char) in s.char_indices() {
+            match (char, delimiter) {
+                _ if escaped => escaped = false,
+                ('\\', Some(_)) => escaped = true,
+                ('/' | '"', None) => delimiter = Some(char),
+                (char, Some(open)) if char == open => delimiter = None,
+                (',', None) => {
+                    specs.push(parse(&s[start..index])?);
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        specs.push(parse(&s[start..])?);
+        Ok(Self(specs))
+    }
+}
+
+/// Resolves `specs` for a file and sorts them by their start. Overlapping ranges are merged, as
+/// are adjacent ones unless a column splits the line between them. Inline ranges are never
+/// merged, so that the elision before them is kept.
+pub fn resolve<'spec>(
+    specs: impl IntoIterator<Item = &'spec RangeSpec>,
+    lines: &[&str],
+) -> Result<Vec<Range>> {
+    let mut ranges = vec![];
+    for spec in specs {
+        ranges.push(spec.resolve(lines)?);
+    }
+    ranges.sort_by_key(|range| (range.start, range.start_col));
+    let mut merged: Vec<Range> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if can_merge(last, &range) => {
+                if range.end > last.end {
+                    last.end = range.end;
+                    last.end_col = range.end_col;
+                } else if range.end == last.end {
+                    last.end_col = last.end_col.zip(range.end_col).map(|(a, b)| a.max(b));
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+/// Whether `range`, which does not start before `last`, overlaps or touches it
+fn can_merge(last: &Range, range: &Range) -> bool {
+    if last.inline || range.inline {
+        return false;
+    }
+    match range.start.cmp(&last.end) {
+        Ordering::Less => true,
+        // a missing column is the start or end of the line
+        Ordering::Equal => match (last.end_col, range.start_col) {
+            (Some(end), Some(start)) => start <= end,
+            _ => true,
+        },
+        Ordering::Greater => {
+            range.start == last.end + 1 && last.end_col.is_none() && range.start_col.is_none()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, Default)]
 pub enum Offset {
     #[default]
@@ -97,7 +452,279 @@ impl Add<usize> for Offset {
         match self {
             Offset::None => rhs,
             Offset::Positive(num) => rhs + num,
-            Offset::Negative(num) => rhs - num,
+            Offset::Negative(num) => rhs.saturating_sub(num),
         }
     }
 }
+
+/// What the columns of ranges count. Tabs are expanded before columns are counted, so a tab
+/// counts as the spaces replacing it in both units.
+#[derive(Deserialize, JsonSchema, Clone, Copy, Hash, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnUnit {
+    /// Unicode characters
+    #[default]
+    Chars,
+    /// Display cells, in which wide characters like CJK ideographs take up two columns and
+    /// combining marks none
+    Cells,
+}
+
+impl ColumnUnit {
+    fn char_width(self, char: char) -> usize {
+        match self {
+            ColumnUnit::Chars => 1,
+            ColumnUnit::Cells => cell_width(char),
+        }
+    }
+
+    /// The byte index in `line` at which column `col` starts, `None` if the line is shorter. A
+    /// column in the middle of a wide character refers to the start of that character.
+    pub fn byte_index(self, line: &str, col: usize) -> Option<usize> {
+        let mut position = 0;
+        for (index, char) in line.char_indices() {
+            let width = self.char_width(char);
+            if position + width > col {
+                return Some(index);
+            }
+            position += width;
+        }
+        (position == col).then_some(line.len())
+    }
+
+    /// The number of columns of `line`
+    pub fn width(self, line: &str) -> usize {
+        line.chars().map(|char| self.char_width(char)).sum()
+    }
+}
+
+impl fmt::Display for ColumnUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnUnit::Chars => write!(f, "characters"),
+            ColumnUnit::Cells => write!(f, "cells"),
+        }
+    }
+}
+
+/// The display width of a character, covering the common wide and zero width blocks
+fn cell_width(char: char) -> usize {
+    match char as u32 {
+        // combining marks, zero width spaces and joiners, variation selectors
+        0x0300..=0x036F
+        | 0x0483..=0x0489
+        | 0x0591..=0x05BD
+        | 0x0610..=0x061A
+        | 0x064B..=0x065F
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => 0,
+        // Hangul Jamo, CJK, Hangul syllables, fullwidth forms and emoji
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Replaces tabs with spaces up to the next multiple of `tab_width` display cells, a width of 0
+/// keeps them. ANSI escape sequences do not take up any cells.
+pub fn expand_tabs(code: &str, tab_width: usize) -> String {
+    if tab_width == 0 || !code.contains('\t') {
+        return code.to_string();
+    }
+    let mut out = String::with_capacity(code.len());
+    let mut position = 0;
+    let mut in_escape = false;
+    for char in code.chars() {
+        match char {
+            // escape sequences end with a letter, like `m` for colors
+            _ if in_escape => {
+                out.push(char);
+                in_escape = !char.is_ascii_alphabetic();
+            }
+            '\x1b' => {
+                out.push(char);
+                in_escape = true;
+            }
+            '\t' => {
+                let spaces = tab_width - position % tab_width;
+                out += &" ".repeat(spaces);
+                position += spaces;
+            }
+            '\n' => {
+                out.push(char);
+                position = 0;
+            }
+            char => {
+                out.push(char);
+                position += cell_width(char);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: &[&str] = &[
+        "fn main() {",
+        "    let a = foo(1, 2, 3);",
+        "    let b = bar();",
+        "    if a == b {",
+        "        baz();",
+        "    }",
+        "}",
+        "",
+        "fn foo() {}",
+        "fn bar() {}",
+    ];
+
+    /// The resolved ranges as `(start, end)` rows
+    fn rows(ranges: &str) -> Vec<(usize, usize)> {
+        resolve_all(ranges)
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    fn resolve_all(ranges: &str) -> Vec<Range> {
+        let ranges: Ranges = ranges.parse().unwrap();
+        resolve(&ranges.0, LINES).unwrap()
+    }
+
+    #[test]
+    fn counts_columns_in_chars_or_cells() {
+        let line = "é中x";
+        assert_eq!(ColumnUnit::Chars.byte_index(line, 2), Some(5));
+        assert_eq!(ColumnUnit::Chars.width(line), 3);
+        assert_eq!(ColumnUnit::Cells.byte_index(line, 1), Some(2));
+        // a column inside of a wide character refers to its start
+        assert_eq!(ColumnUnit::Cells.byte_index(line, 2), Some(2));
+        assert_eq!(ColumnUnit::Cells.byte_index(line, 3), Some(5));
+        assert_eq!(ColumnUnit::Cells.byte_index(line, 4), Some(6));
+        assert_eq!(ColumnUnit::Cells.byte_index(line, 5), None);
+        assert_eq!(ColumnUnit::Cells.width(line), 4);
+    }
+
+    #[test]
+    fn expands_tabs_to_the_next_stop() {
+        assert_eq!(expand_tabs("\ta\tb", 4), "    a   b");
+        assert_eq!(expand_tabs("ab\n\tc", 4), "ab\n    c");
+        assert_eq!(
+            expand_tabs("\x1b[31mab\x1b[0m\tc", 4),
+            "\x1b[31mab\x1b[0m  c"
+        );
+        assert_eq!(expand_tabs("\ta", 0), "\ta");
+    }
+
+    #[test]
+    fn closed_and_single_line_ranges() {
+        assert_eq!(rows("2-4"), [(1, 3)]);
+        assert_eq!(rows("2..4"), [(1, 3)]);
+        assert_eq!(rows("5"), [(4, 4)]);
+        assert!("0".parse::<RangeSpec>().is_err());
+        assert!("4-2".parse::<RangeSpec>().unwrap().resolve(LINES).is_err());
+    }
+
+    #[test]
+    fn open_ranges() {
+        assert_eq!(rows("8-"), [(7, 9)]);
+        assert_eq!(rows("8 - "), [(7, 9)]);
+    }
+
+    #[test]
+    fn negative_ranges() {
+        assert_eq!(rows("-3-"), [(7, 9)]);
+        assert_eq!(rows("-3--2"), [(7, 8)]);
+        assert_eq!(rows("$-2-$"), [(7, 9)]);
+        assert_eq!(rows("$"), [(9, 9)]);
+        assert!("-0".parse::<RangeSpec>().is_err());
+    }
+
+    #[test]
+    fn indent_offsets() {
+        let spec: RangeSpec = "+2 3-4".parse().unwrap();
+        assert!(matches!(spec.indent_offset, Offset::Positive(2)));
+        let spec: RangeSpec = "-2 3-4".parse().unwrap();
+        assert!(matches!(spec.indent_offset, Offset::Negative(2)));
+        assert!(matches!(spec.start, Line::FromStart(2)));
+        assert_eq!(Offset::Negative(4) + 2, 0);
+    }
+
+    #[test]
+    fn inline_ranges() {
+        let spec: RangeSpec = "_2:24-3".parse().unwrap();
+        assert!(spec.inline);
+        assert_eq!(spec.start_col, Some(24));
+        assert_eq!(spec.end_col, None);
+        assert!(matches!(spec.end, End::Line(Line::FromStart(2))));
+    }
+
+    #[test]
+    fn sorts_and_merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(rows("9-10,1-3,2-5"), [(0, 4), (8, 9)]);
+        assert_eq!(rows("1-2,3-4"), [(0, 3)]);
+        assert_eq!(rows("1-2,4-5"), [(0, 1), (3, 4)]);
+    }
+
+    #[test]
+    fn merges_same_line_columns_only_if_they_touch() {
+        let merged = resolve_all("2:4-2:9,2:9-2:17");
+        assert_eq!(merged.len(), 1);
+        assert_eq!(
+            (merged[0].start_col, merged[0].end_col),
+            (Some(4), Some(17))
+        );
+
+        assert_eq!(resolve_all("2:4-2:9,2:12-2:17").len(), 2);
+        // a column splits the line from the following one
+        assert_eq!(resolve_all("1-2:9,3-4").len(), 2);
+    }
+
+    #[test]
+    fn never_merges_inline_ranges() {
+        let ranges = resolve_all("2:4-2:17,_2:24-3");
+        assert_eq!(ranges.len(), 2);
+        assert!(!ranges[0].inline);
+        assert!(ranges[1].inline);
+        assert_eq!((ranges[1].start, ranges[1].start_col), (1, Some(24)));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(rows("/^fn main/../^}/"), [(0, 6)]);
+        assert_eq!(rows("\"fn \"#2"), [(8, 8)]);
+        assert_eq!(rows("\"let b\"-1..\"let b\"+1"), [(1, 3)]);
+        // ends are searched from the start of the range on
+        assert_eq!(rows("/baz/../}/"), [(4, 5)]);
+        assert!("/(/".parse::<RangeSpec>().is_err());
+        assert!("\"missing\""
+            .parse::<RangeSpec>()
+            .unwrap()
+            .resolve(LINES)
+            .is_err());
+    }
+
+    #[test]
+    fn commas_inside_anchors_do_not_split() {
+        let ranges: Ranges = "\"foo(1, 2\",9-10".parse().unwrap();
+        assert_eq!(ranges.0.len(), 2);
+        assert_eq!(rows("\"foo(1, 2\",9-10"), [(1, 1), (8, 9)]);
+    }
+}
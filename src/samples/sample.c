@@ -0,0 +1,18 @@
+#include <stdio.h>
+
+#define MAX 10
+
+/* sum the first n integers */
+static int sum(const int *values, size_t n) {
+    int total = 0;
+    for (size_t i = 0; i < n; i++) {
+        total += values[i];
+    }
+    return total;
+}
+
+int main(void) {
+    int values[MAX] = {1, 2, 3};
+    printf("%d\n", sum(values, 3));
+    return 0;
+}
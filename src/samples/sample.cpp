@@ -0,0 +1,13 @@
+#include <vector>
+
+namespace geo {
+template <typename T>
+class Stack {
+  public:
+    void push(T value) { items.push_back(value); }
+    bool empty() const noexcept { return items.empty(); }
+
+  private:
+    std::vector<T> items;
+};
+} // namespace geo
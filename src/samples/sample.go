@@ -0,0 +1,18 @@
+package main
+
+import "fmt"
+
+// Point is a point in space
+type Point struct {
+	X, Y int
+}
+
+func (p Point) String() string {
+	return fmt.Sprintf("(%d, %d)", p.X, p.Y)
+}
+
+func main() {
+	for i := 0; i < 3; i++ {
+		fmt.Println(Point{X: i, Y: -i}, true, nil)
+	}
+}
@@ -0,0 +1,16 @@
+package geo;
+
+import java.util.List;
+
+/** A point in space. */
+public record Point(int x, int y) {
+    @Override
+    public String toString() {
+        // format both coordinates
+        return "(" + x + ", " + y + ")";
+    }
+
+    static int sum(List<Point> points) {
+        return points.stream().mapToInt(Point::x).sum();
+    }
+}
@@ -0,0 +1,16 @@
+import { readFile } from 'fs/promises';
+
+// count the lines of a file
+export async function countLines(path) {
+  const text = await readFile(path, 'utf8');
+  return text.split(/\r?\n/).length;
+}
+
+class Counter {
+  #count = 0;
+  increment() {
+    return ++this.#count;
+  }
+}
+
+console.log(new Counter().increment(), null, true);
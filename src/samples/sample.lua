@@ -0,0 +1,13 @@
+-- a simple class
+local Point = {}
+Point.__index = Point
+
+function Point.new(x, y)
+  return setmetatable({ x = x, y = y }, Point)
+end
+
+for i = 1, 3 do
+  if i ~= 2 then
+    print(Point.new(i, -i).x, nil, true)
+  end
+end
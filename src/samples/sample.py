@@ -0,0 +1,18 @@
+import math
+from dataclasses import dataclass
+
+
+@dataclass
+class Circle:
+    """A circle with a radius."""
+
+    radius: float = 1.0
+
+    def area(self) -> float:
+        # the usual formula
+        return math.pi * self.radius ** 2
+
+
+for r in range(3):
+    if r > 0:
+        print(f"{r}: {Circle(r).area():.2f}")
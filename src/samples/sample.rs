@@ -0,0 +1,24 @@
+use std::fmt;
+
+/// A point in space
+#[derive(Debug, Clone, Copy)]
+pub struct Point<T> {
+    x: T,
+    y: T,
+}
+
+impl<T: fmt::Display> Point<T> {
+    pub fn show(&self) -> String {
+        // format both coordinates
+        format!("({}, {})", self.x, self.y)
+    }
+}
+
+fn main() {
+    let points = [Point { x: 1, y: 2 }, Point { x: -3, y: 4 }];
+    for (i, p) in points.iter().enumerate() {
+        if i > 0 && p.x < 0 {
+            println!("{i}: {}", p.show());
+        }
+    }
+}
@@ -0,0 +1,10 @@
+#!/bin/sh
+set -eu
+
+# build every chapter
+for file in chapters/*.tex; do
+    if [ -f "$file" ]; then
+        echo "building ${file%.tex}" >&2
+        lualatex --shell-escape "$file"
+    fi
+done
@@ -0,0 +1,16 @@
+interface Shape {
+  readonly name: string;
+  area(): number;
+}
+
+// a square with a given side length
+export class Square implements Shape {
+  readonly name = 'square';
+  constructor(private side: number) {}
+
+  area(): number {
+    return this.side ** 2;
+  }
+}
+
+const shapes: Shape[] = [new Square(2)];
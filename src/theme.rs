@@ -1,154 +1,437 @@
+use std::{collections::BTreeMap, iter};
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Clone, Hash, Debug)]
+use crate::{color::Rgb, config};
+
+/// Capture names renamed by nvim-treesitter as `(old, new)` pairs. Theme keys are looked up
+/// under both names so that old themes work with new queries and vice versa.
+const CAPTURE_ALIASES: &[(&str, &str)] = &[
+    ("conditional", "keyword.conditional"),
+    ("danger", "comment.error"),
+    ("debug", "keyword.debug"),
+    ("define", "keyword.directive.define"),
+    ("exception", "keyword.exception"),
+    ("field", "variable.member"),
+    ("float", "number.float"),
+    ("include", "keyword.import"),
+    ("method", "function.method"),
+    ("method.call", "function.method.call"),
+    ("namespace", "module"),
+    ("note", "comment.note"),
+    ("parameter", "variable.parameter"),
+    ("preproc", "keyword.directive"),
+    ("repeat", "keyword.repeat"),
+    ("storageclass", "keyword.modifier"),
+    ("string.regex", "string.regexp"),
+    ("symbol", "string.special.symbol"),
+    ("text.diff.add", "diff.plus"),
+    ("text.diff.delete", "diff.minus"),
+    ("text.emphasis", "markup.italic"),
+    ("text.environment", "markup.environment"),
+    ("text.environment.name", "markup.environment.name"),
+    ("text.literal", "markup.raw"),
+    ("text.math", "markup.math"),
+    ("text.quote", "markup.quote"),
+    ("text.reference", "markup.link"),
+    ("text.strike", "markup.strikethrough"),
+    ("text.strong", "markup.strong"),
+    ("text.title", "markup.heading"),
+    ("text.todo", "comment.todo"),
+    ("text.underline", "markup.underline"),
+    ("text.uri", "markup.link.url"),
+    ("text.uri", "string.special.url"),
+    ("type.qualifier", "keyword.modifier"),
+    ("warning", "comment.warning"),
+];
+
+/// Captures which only carry meaning for editors and never get a theme entry.
+const IGNORED_CAPTURES: &[&str] = &["spell", "nospell", "conceal"];
+
+pub fn is_ignored_capture(capture: &str) -> bool {
+    capture.starts_with('_') || IGNORED_CAPTURES.contains(&capture)
+}
+
+/// Finds the theme key for a capture name, trying the name and its aliases before falling back
+/// to less specific names by removing trailing components, e.g. `function.method.call`, then
+/// `function.method` and finally `function`.
+pub fn resolve_capture<'theme>(
+    theme: &'theme BTreeMap<String, ThemeValue>,
+    user_aliases: &BTreeMap<String, String>,
+    capture: &str,
+) -> Option<&'theme str> {
+    let mut name = capture;
+    loop {
+        let aliases = user_aliases
+            .iter()
+            .map(|(old, new)| (old.as_str(), new.as_str()))
+            .chain(CAPTURE_ALIASES.iter().copied())
+            .filter_map(|(old, new)| {
+                if old == name {
+                    Some(new)
+                } else if new == name {
+                    Some(old)
+                } else {
+                    None
+                }
+            });
+        if let Some((key, _)) = iter::once(name)
+            .chain(aliases)
+            .find_map(|candidate| theme.get_key_value(candidate))
+        {
+            return Some(key);
+        }
+        name = name.rsplit_once('.')?.0;
+    }
+}
+
+/// Rainbow identifiers and brackets cycle through the theme keys `rainbow.1`, `rainbow.2` and so
+/// on, as many as the theme defines without gaps
+const RAINBOW_PREFIX: &str = "rainbow.";
+
+pub fn rainbow_palette_len(theme: &BTreeMap<String, ThemeValue>) -> usize {
+    (1..)
+        .take_while(|n| theme.contains_key(&format!("{RAINBOW_PREFIX}{n}")))
+        .count()
+}
+
+/// The key for the item at `index` in a palette of `len` keys
+pub fn rainbow_key(index: usize, len: usize) -> String {
+    format!("{RAINBOW_PREFIX}{}", index % len + 1)
+}
+
+/// An entry of the `themes` config section. Its entries are layered on top of the theme it
+/// `inherits` from, or on top of the top-level `theme` if it does not inherit from any.
+#[derive(Deserialize, JsonSchema, Clone, Hash, Debug)]
+pub struct NamedTheme {
+    pub inherits: Option<String>,
+    #[serde(flatten)]
+    pub entries: BTreeMap<String, ThemeValue>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Hash, Debug)]
 #[serde(untagged)]
 pub enum ThemeValue {
+    /// `#rrggbb` or a link to another key as `$key`
     Color(String),
-    Object {
-        color: Option<String>,
-        #[serde(default)]
-        underline: bool,
-        #[serde(default)]
-        strikethrough: bool,
-        #[serde(default)]
-        italic: bool,
-        #[serde(default)]
-        bold: bool,
-        link: Option<String>,
-    },
+    Object(Style),
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Hash, Debug, Default)]
+#[serde(default)]
+pub struct Style {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub underline: bool,
+    /// Implies `underline`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underline_style: Option<UnderlineStyle>,
+    /// Implies `underline`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underline_color: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub overline: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub smallcaps: bool,
+    /// Keeps the surrounding text color, even if a linked entry specifies one
+    #[serde(skip_serializing_if = "is_false")]
+    pub nocolor: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Hash, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnderlineStyle {
+    Solid,
+    Double,
+    Wavy,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The `ulem` command drawing this kind of underline
+    fn command(self) -> &'static str {
+        match self {
+            UnderlineStyle::Solid => "×uline",
+            UnderlineStyle::Double => "×uuline",
+            UnderlineStyle::Wavy => "×uwave",
+            UnderlineStyle::Dotted => "×dotuline",
+            UnderlineStyle::Dashed => "×dashuline",
+        }
+    }
+}
+
+impl Style {
+    /// Keys of `Style` as listed by its schema, used by `doctor` to report unknown keys
+    pub fn keys() -> Vec<String> {
+        config::property_names(&schemars::schema_for!(Style))
+    }
+
+    fn underline_style(&self) -> Option<UnderlineStyle> {
+        match (
+            self.underline_style,
+            self.underline || self.underline_color.is_some(),
+        ) {
+            (Some(style), _) => Some(style),
+            (None, true) => Some(UnderlineStyle::Solid),
+            (None, false) => None,
+        }
+    }
 }
 
 impl ThemeValue {
+    /// Merges this value over the one it links to. Options set here take precedence and flags
+    /// are enabled if they are enabled in either value.
     pub fn linked_to(&self, other: &Self) -> Self {
-        match (self, other) {
-            (ThemeValue::Color(_), _) => other.clone(),
-            (
-                ThemeValue::Object {
-                    color: Some(color),
-                    underline,
-                    strikethrough,
-                    italic,
-                    bold,
-                    link: _,
-                },
-                ThemeValue::Color(_),
-            )
-            | (
-                ThemeValue::Object {
-                    color: None,
-                    underline,
-                    strikethrough,
-                    italic,
-                    bold,
-                    link: _,
-                },
-                ThemeValue::Color(color),
-            ) => Self::Object {
+        let ThemeValue::Object(style) = self else {
+            return other.clone();
+        };
+        let other = match other {
+            ThemeValue::Color(color) => Style {
                 color: Some(color.clone()),
-                underline: *underline,
-                strikethrough: *strikethrough,
-                italic: *italic,
-                bold: *bold,
-                link: None,
+                ..Style::default()
             },
-            (
-                ThemeValue::Object {
-                    color: color @ Some(_),
-                    underline,
-                    strikethrough,
-                    italic,
-                    bold,
-                    link: _,
-                },
-                ThemeValue::Object {
-                    color: _,
-                    underline: other_underline,
-                    strikethrough: other_strikethrough,
-                    italic: other_italic,
-                    bold: other_bold,
-                    link,
-                },
-            )
-            | (
-                ThemeValue::Object {
-                    color: None,
-                    underline,
-                    strikethrough,
-                    italic,
-                    bold,
-                    link: _,
-                },
-                ThemeValue::Object {
-                    color,
-                    underline: other_underline,
-                    strikethrough: other_strikethrough,
-                    italic: other_italic,
-                    bold: other_bold,
-                    link,
-                },
-            ) => Self::Object {
-                color: color.clone(),
-                underline: *underline || *other_underline,
-                strikethrough: *strikethrough || *other_strikethrough,
-                italic: *italic || *other_italic,
-                bold: *bold || *other_bold,
-                link: link.clone(),
+            ThemeValue::Object(other) => other.clone(),
+        };
+        Self::Object(Style {
+            color: match style.nocolor {
+                true => None,
+                false => style.color.clone().or(other.color),
             },
+            background: style.background.clone().or(other.background),
+            underline: style.underline || other.underline,
+            underline_style: style.underline_style.or(other.underline_style),
+            underline_color: style.underline_color.clone().or(other.underline_color),
+            strikethrough: style.strikethrough || other.strikethrough,
+            overline: style.overline || other.overline,
+            italic: style.italic || other.italic,
+            bold: style.bold || other.bold,
+            smallcaps: style.smallcaps || other.smallcaps,
+            nocolor: style.nocolor || (other.nocolor && style.color.is_none()),
+            link: other.link,
+        })
+    }
+
+    pub fn link(&self) -> Option<&str> {
+        match self {
+            ThemeValue::Color(str) => str.strip_prefix('$'),
+            ThemeValue::Object(Style { link, .. }) => link.as_deref(),
+        }
+    }
+
+    /// Checks that every color of this value is `#rrggbb`, so that it can be written to TeX
+    pub fn validate(&self) -> Result<()> {
+        if let ThemeValue::Color(color) = self {
+            if color.starts_with('$') {
+                return Ok(());
+            }
         }
+        for (_, color) in self.colors() {
+            if Rgb::from_hex(color).is_none() {
+                bail!("malformed color `{color}`, expected `#rrggbb`");
+            }
+        }
+        Ok(())
     }
 
     pub fn write(&self, text: &str) -> String {
+        let (open, brace_count) =
+            self.open_commands(&|_, color| format!("[HTML]{{{}}}", &color[1..]));
         let text = text.replace('{', "×{").replace('}', "×}");
         let lines: Vec<_> = text
             .lines()
-            .map(|line| {
-                let mut out = String::new();
-                match self {
-                    ThemeValue::Color(color)
-                    | ThemeValue::Object {
-                        color: Some(color),
-                        underline: false,
-                        strikethrough: false,
-                        italic: false,
-                        bold: false,
-                        link: _,
-                    } => {
-                        out +=
-                            &format!("×textcolor[HTML]{{{color}}}{{{line}}}", color = &color[1..])
-                    }
-                    ThemeValue::Object {
-                        color: Some(color),
-                        underline,
-                        strikethrough,
-                        italic,
-                        bold,
-                        link: _,
-                    } => {
-                        out += &format!("×textcolor[HTML]{{{color}}}{{", color = &color[1..]);
-                        let mut brace_count = 1;
-                        if *underline {
-                            out += "×uline{";
-                            brace_count += 1;
-                        }
-                        if *strikethrough {
-                            out += "×sout{";
-                            brace_count += 1;
-                        }
-                        if *italic {
-                            out += "×textit{";
-                            brace_count += 1;
-                        }
-                        if *bold {
-                            out += "×textbf{";
-                            brace_count += 1;
-                        }
-                        out += &format!("{line}{braces}", braces = "}".repeat(brace_count));
-                    }
-                    ThemeValue::Object { color: None, .. } => {}
-                }
-                out
-            })
+            .map(|line| format!("{open}{line}{}", "}".repeat(brace_count)))
             .collect();
         lines.join("\n")
     }
+
+    /// All colors used by this value together with a suffix distinguishing their roles
+    fn colors(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            ThemeValue::Color(color) => vec![("", color)],
+            ThemeValue::Object(style) => [
+                ("", style.color.as_deref()),
+                ("@bg", style.background.as_deref()),
+                ("@ul", style.underline_color.as_deref()),
+            ]
+            .into_iter()
+            .filter_map(|(suffix, color)| Some((suffix, color?)))
+            .collect(),
+        }
+    }
+
+    /// Returns the commands opening this style and the number of braces closing them again.
+    /// `color_arg` turns the role suffix (see `colors`) and the color into everything passed to
+    /// a color command except for the text itself.
+    fn open_commands(&self, color_arg: &dyn Fn(&str, &str) -> String) -> (String, usize) {
+        let mut out = String::new();
+        let mut brace_count = 0;
+        let style = match self {
+            ThemeValue::Color(color) => {
+                return (format!("×textcolor{}{{", color_arg("", color)), 1);
+            }
+            ThemeValue::Object(style) => style,
+        };
+        if let Some(background) = &style.background {
+            out += &format!("×colorbox{}{{", color_arg("@bg", background));
+            brace_count += 1;
+        }
+        if let Some(color) = &style.color {
+            out += &format!("×textcolor{}{{", color_arg("", color));
+            brace_count += 1;
+        }
+        if let Some(underline_style) = style.underline_style() {
+            match &style.underline_color {
+                Some(underline_color) => {
+                    out += &format!(
+                        "×LirstUnderline{{{}}}{{{}}}{{",
+                        underline_style.command(),
+                        color_arg("@ul", underline_color)
+                    )
+                }
+                None => out += &format!("{}{{", underline_style.command()),
+            }
+            brace_count += 1;
+        }
+        for (enabled, command) in [
+            (style.strikethrough, "×sout{"),
+            (style.overline, "×LirstOverline{"),
+            (style.italic, "×textit{"),
+            (style.bold, "×textbf{"),
+            (style.smallcaps, "×textsc{"),
+        ] {
+            if enabled {
+                out += command;
+                brace_count += 1;
+            }
+        }
+        (out, brace_count)
+    }
+}
+
+/// Writes text referring to the style of the theme key by name, so that the style itself can be
+/// (re)defined in the document preamble with the output of `tex_definitions`.
+pub fn write_semantic(key: &str, text: &str) -> String {
+    let text = text.replace('{', "×{").replace('}', "×}");
+    let lines: Vec<_> = text
+        .lines()
+        .map(|line| format!("×LirstStyle{{{key}}}{{{line}}}"))
+        .collect();
+    lines.join("\n")
+}
+
+/// Returns `×definecolor` commands and style macros for every entry of a resolved theme as used
+/// by `×LirstStyle`. `param` is the macro parameter character, which must be doubled when the
+/// definitions are themselves part of a macro body.
+pub fn tex_definitions(theme: &BTreeMap<String, ThemeValue>, param: &str) -> String {
+    let mut out = String::new();
+    for (key, value) in theme {
+        for (suffix, color) in value.colors() {
+            out += &format!(
+                "×definecolor{{lirst@{key}{suffix}}}{{HTML}}{{{}}}%\n",
+                &color[1..]
+            );
+        }
+        let (open, brace_count) =
+            value.open_commands(&|suffix, _| format!("{{lirst@{key}{suffix}}}"));
+        out += &format!(
+            "×expandafter×def×csname lirst@style@{key}×endcsname{param}1{{{open}{param}1{}}}%\n",
+            "}".repeat(brace_count)
+        );
+    }
+    out.replace('×', "\\")
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn theme(keys: &[&str]) -> BTreeMap<String, ThemeValue> {
+        keys.iter()
+            .map(|key| (key.to_string(), ThemeValue::Color("#000000".to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn falls_back_to_less_specific_captures() {
+        let theme = theme(&["function", "function.method", "keyword"]);
+        let resolve = |capture| resolve_capture(&theme, &BTreeMap::new(), capture);
+        assert_eq!(resolve("function.method.call"), Some("function.method"));
+        assert_eq!(resolve("function.builtin"), Some("function"));
+        assert_eq!(resolve("keyword"), Some("keyword"));
+        assert_eq!(resolve("variable.parameter"), None);
+    }
+
+    #[test]
+    fn resolves_renamed_captures_both_ways() {
+        let old = theme(&["method", "conditional"]);
+        let resolve = |capture| resolve_capture(&old, &BTreeMap::new(), capture);
+        assert_eq!(resolve("function.method"), Some("method"));
+        assert_eq!(resolve("keyword.conditional.ternary"), Some("conditional"));
+
+        let new = theme(&["function.method", "module"]);
+        let resolve = |capture| resolve_capture(&new, &BTreeMap::new(), capture);
+        assert_eq!(resolve("method"), Some("function.method"));
+        assert_eq!(resolve("namespace"), Some("module"));
+    }
+
+    #[test]
+    fn prefers_exact_keys_over_aliases() {
+        let theme = theme(&["method", "function.method"]);
+        let resolve = |capture| resolve_capture(&theme, &BTreeMap::new(), capture);
+        assert_eq!(resolve("method"), Some("method"));
+        assert_eq!(resolve("function.method"), Some("function.method"));
+    }
+
+    #[test]
+    fn uses_user_aliases() {
+        let theme = theme(&["keyword"]);
+        let aliases = BTreeMap::from([("keyword".to_string(), "storage".to_string())]);
+        assert_eq!(
+            resolve_capture(&theme, &aliases, "storage.type"),
+            Some("keyword")
+        );
+        assert_eq!(
+            resolve_capture(&theme, &BTreeMap::new(), "storage.type"),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_editor_only_captures() {
+        assert!(is_ignored_capture("spell"));
+        assert!(is_ignored_capture("_name"));
+        assert!(!is_ignored_capture("string"));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        let color = |color: &str| ThemeValue::Color(color.to_string());
+        assert!(color("#a626a4").validate().is_ok());
+        assert!(color("$keyword").validate().is_ok());
+        assert!(color("").validate().is_err());
+        assert!(color("a626a4").validate().is_err());
+        let style = ThemeValue::Object(Style {
+            underline_color: Some(String::new()),
+            ..Style::default()
+        });
+        assert!(style.validate().is_err());
+    }
 }
@@ -1,101 +1,248 @@
-use std::{collections::HashMap, fs, ops::RangeInclusive, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{bail, Context, Result};
-use tree_sitter::{Language, Query, QueryPredicateArg};
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor, Tree};
 use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent, Highlighter};
-use tree_sitter_loader::Loader;
+use tree_sitter_loader::{LanguageConfiguration, Loader};
 
-use crate::{config::Config, output::Output, theme::ThemeValue, Cli, Command};
+use crate::{
+    brackets, bundled,
+    config::Config,
+    locals,
+    output::Output,
+    query,
+    theme::{self, ThemeValue},
+    Cli, Command,
+};
+
+/// Replaced by the snippet in inline templates
+pub const TEMPLATE_PLACEHOLDER: &str = "{code}";
+/// Theme entry layered on top of syntax errors with `underline_errors`
+const ERROR_UNDERLINE_KEY: &str = "error.underline";
 
 pub struct Settings {
     pub lang: Language,
-    pub highlight_names: Vec<String>,
-    pub highlight_styles: Vec<ThemeValue>,
+    pub theme: BTreeMap<String, ThemeValue>,
+    pub capture_aliases: BTreeMap<String, String>,
+    pub semantic_styles: bool,
+    pub scope_highlights: bool,
+    pub rainbow_identifiers: bool,
+    pub rainbow_brackets: bool,
+    pub underline_errors: bool,
+    /// The inline template wrapping the code, if any
+    pub template: Option<String>,
 
     pub highlights_query: String,
     pub injection_query: String,
     pub locals_query: String,
+    /// Whether the highlights, injection and locals queries were read from the query search
+    /// dirs. Those are Neovim queries passed through `query::process`, embedded queries are
+    /// used as they are.
+    pub neovim_queries: [bool; 3],
+    /// Only used by `outline`, so it is not passed through `query::process`
+    pub tags_query: String,
 }
 
 pub fn get_settings(config: Config, subcommand: &Command) -> Result<Settings> {
-    let mut highlight_names = Vec::with_capacity(config.theme.len());
-    let mut highlight_styles = Vec::with_capacity(config.theme.len());
-    for (key, value) in config.theme.into_iter() {
-        highlight_names.push(key);
-        highlight_styles.push(value);
-    }
-
-    let mut loader = Loader::new()?;
-    loader.configure_highlights(&highlight_names);
-    loader.find_all_languages(&tree_sitter_loader::Config {
-        parser_directories: config.parser_search_dirs,
-    })?;
-
-    let (lang, lang_config) = match match &subcommand {
-        Command::TreeSitter { file, .. } => loader.language_configuration_for_file_name(file)?,
-        Command::Inline { file_ext, .. } => loader
-            .language_configuration_for_file_name(&PathBuf::from(format!("file.{file_ext}")))?,
+    let (file_name, template) = match &subcommand {
+        Command::TreeSitter { file, .. } | Command::Outline { file, .. } => (file.clone(), None),
+        Command::Inline {
+            file_ext, context, ..
+        } => (
+            PathBuf::from(format!("file.{file_ext}")),
+            inline_template(&config, file_ext, context.as_deref())?,
+        ),
         Command::Ansi { .. } => panic!("`ts::get_settings` called with `ansi` subcommand"),
         Command::TexInclude => unreachable!("`tex-include` subcommand immediately returns"),
         Command::FromTex { .. } => unreachable!("`from-tex` subcommand immediately returns"),
-    } {
-        Some(conf) => conf,
-        None => {
-            bail!("No matching tree-sitter configuration found");
-        }
+        Command::Theme { .. } => unreachable!("`theme` subcommands immediately return"),
+        Command::Doctor => unreachable!("`doctor` subcommand immediately returns"),
+        Command::Config { .. } => unreachable!("`config` subcommands immediately return"),
+        Command::Grammar { .. } => unreachable!("`grammar` subcommands immediately return"),
     };
+    let mut settings = settings_for_file(config, &file_name)?;
+    settings.template = template;
+    Ok(settings)
+}
 
-    let parser_name = match lang_config.scope.as_ref() {
-        Some(scope) => scope.replace("source.", ""),
-        None => bail!("Parser has no scope specified"),
+/// Looks up the template for inline code of a file type, using the `default` context if none is
+/// given
+fn inline_template(
+    config: &Config,
+    file_ext: &str,
+    context: Option<&str>,
+) -> Result<Option<String>> {
+    let templates = config.inline_templates.get(file_ext);
+    let Some(context) = context else {
+        return Ok(templates
+            .and_then(|templates| templates.get("default"))
+            .cloned());
     };
+    match templates.and_then(|templates| templates.get(context)) {
+        Some(template) => Ok(Some(template.clone())),
+        None => bail!(
+            "no inline template `{context}` for `.{file_ext}` files, available are: {}",
+            templates
+                .map(|templates| templates
+                    .keys()
+                    .map(|key| format!("`{key}`"))
+                    .collect::<Vec<_>>()
+                    .join(", "))
+                .filter(|keys| !keys.is_empty())
+                .unwrap_or("none".to_string())
+        ),
+    }
+}
 
-    let mut highlights_query = String::new();
-    let mut injection_query = String::new();
-    let mut locals_query = String::new();
+/// Creates a loader for the grammars in `parser_search_dirs`. Entries are either directories
+/// containing `tree-sitter-*` grammar repositories or, like those added by `grammar add`, a
+/// grammar repository themselves.
+pub fn loader(config: &Config) -> Result<Loader> {
+    let mut loader = Loader::new()?;
+    loader.configure_highlights(&config.theme.keys().cloned().collect::<Vec<_>>());
+    let (grammar_dirs, parser_directories): (Vec<_>, Vec<_>) = config
+        .parser_search_dirs
+        .iter()
+        .cloned()
+        .partition(|dir| is_grammar_dir(dir));
+    // the loader warns about an empty list, which is fine if there are grammar dirs
+    if !parser_directories.is_empty() || grammar_dirs.is_empty() {
+        loader.find_all_languages(&tree_sitter_loader::Config { parser_directories })?;
+    }
+    for dir in grammar_dirs {
+        loader
+            .find_language_configurations_at_path(&dir)
+            .with_context(|| format!("could not load grammar at `{}`", dir.to_string_lossy()))?;
+    }
+    Ok(loader)
+}
+
+pub fn is_grammar_dir(dir: &Path) -> bool {
+    dir.join("package.json").is_file()
+}
+
+/// Query files in the order expected by `HighlightConfiguration::new`
+pub const QUERY_FILE_NAMES: [&str; 3] = ["highlights.scm", "injections.scm", "locals.scm"];
+
+/// Query file with the definitions listed by `outline`
+pub const TAGS_FILE_NAME: &str = "tags.scm";
+
+pub fn parser_name(lang_config: &LanguageConfiguration) -> Result<String> {
+    match lang_config.scope.as_ref() {
+        Some(scope) => Ok(scope.replace("source.", "")),
+        None => bail!("Parser has no scope specified"),
+    }
+}
+
+/// Finds the files named in `QUERY_FILE_NAMES` for a parser
+pub fn query_files(config: &Config, parser_name: &str) -> Result<[Option<PathBuf>; 3]> {
+    let mut files = [None, None, None];
+    for (file, name) in files.iter_mut().zip(QUERY_FILE_NAMES) {
+        *file = query_file(config, parser_name, name)?;
+    }
+    Ok(files)
+}
+
+/// Finds a query file for a parser. Files in later query search dirs take precedence over
+/// earlier ones.
+pub fn query_file(config: &Config, parser_name: &str, name: &str) -> Result<Option<PathBuf>> {
+    let mut file = None;
     for glob_str in &config.query_search_dirs {
         for dir in glob::glob(glob_str)?.filter_map(Result::ok) {
-            let filetype_dir = dir.join(&parser_name);
-            let highlights_file = filetype_dir.join("highlights.scm");
-            let injection_file = filetype_dir.join("injections.scm");
-            let locals_file = filetype_dir.join("locals.scm");
-
             // TODO: check for `; inherits: x` comments
-            if highlights_file.is_file() {
-                highlights_query = fs::read_to_string(&highlights_file).with_context(|| {
-                    format!("Could not read {}", highlights_file.to_string_lossy())
-                })?;
-            }
-            if injection_file.is_file() {
-                injection_query = fs::read_to_string(&injection_file).with_context(|| {
-                    format!("Could not read {}", injection_file.to_string_lossy())
-                })?;
+            let path = dir.join(parser_name).join(name);
+            if path.is_file() {
+                file = Some(path);
             }
-            if locals_file.is_file() {
-                locals_query = fs::read_to_string(&locals_file)
-                    .with_context(|| format!("Could not read {}", locals_file.to_string_lossy()))?;
+        }
+    }
+    Ok(file)
+}
+
+pub fn settings_for_file(config: Config, file_name: &Path) -> Result<Settings> {
+    let (lang, parser_name, embedded_queries, embedded_tags) =
+        match bundled::for_file_name(file_name) {
+            Some(grammar) => (
+                (grammar.language)(),
+                grammar.name.to_string(),
+                grammar.queries(),
+                grammar.tags_query(),
+            ),
+            None => {
+                let loader = loader(&config)?;
+                let (lang, lang_config) =
+                    match loader.language_configuration_for_file_name(file_name)? {
+                        Some(conf) => conf,
+                        None => {
+                            bail!("No matching tree-sitter configuration found");
+                        }
+                    };
+                (
+                    lang,
+                    parser_name(lang_config)?,
+                    Default::default(),
+                    String::new(),
+                )
             }
+        };
+
+    let mut queries = embedded_queries;
+    let mut neovim_queries = [false; 3];
+    for ((query, neovim), file) in queries
+        .iter_mut()
+        .zip(&mut neovim_queries)
+        .zip(query_files(&config, &parser_name)?)
+    {
+        if let Some(file) = file {
+            *query = fs::read_to_string(&file)
+                .with_context(|| format!("Could not read {}", file.to_string_lossy()))?;
+            *neovim = true;
         }
     }
+    let [highlights_query, injection_query, locals_query] = queries;
+    let tags_query = match query_file(&config, &parser_name, TAGS_FILE_NAME)? {
+        Some(file) => fs::read_to_string(&file)
+            .with_context(|| format!("Could not read {}", file.to_string_lossy()))?,
+        None => embedded_tags,
+    };
 
     Ok(Settings {
         lang,
-        highlight_names,
-        highlight_styles,
+        theme: config.theme,
+        capture_aliases: config.capture_aliases,
+        semantic_styles: config.semantic_styles,
+        scope_highlights: config.scope_highlights,
+        rainbow_identifiers: config.rainbow_identifiers,
+        rainbow_brackets: config.rainbow_brackets,
+        underline_errors: config.underline_errors,
+        template: None,
         highlights_query,
         injection_query,
         locals_query,
+        neovim_queries,
+        tags_query,
     })
 }
 
+/// Highlights `code`, which is taken from the lines `line_numbers` of `file` if it is given
 pub fn highlight(
     code: &str,
+    file: Option<&str>,
     line_numbers: Option<Vec<RangeInclusive<usize>>>,
     cli: &Cli,
     mut settings: Settings,
     file_name: Option<String>,
 ) -> Result<String> {
     let inline = matches!(&cli.subcommand, Command::Inline { .. });
+    // the line in the file of each line of `code`, which only contains the selected ranges
+    let file_lines: Option<Vec<usize>> = line_numbers
+        .as_ref()
+        .map(|numbers| numbers.iter().cloned().flatten().collect());
     let mut output = match line_numbers {
         Some(numbers) => Output::new(
             numbers.into_iter().flatten(),
@@ -113,9 +260,16 @@ pub fn highlight(
             ..
         }
     ) {
-        settings.highlights_query = process_queries(settings.lang, &settings.highlights_query)?;
-        settings.injection_query = process_queries(settings.lang, &settings.injection_query)?;
-        settings.locals_query = process_queries(settings.lang, &settings.locals_query)?;
+        let queries = [
+            &mut settings.highlights_query,
+            &mut settings.injection_query,
+            &mut settings.locals_query,
+        ];
+        for (query, neovim) in queries.into_iter().zip(settings.neovim_queries) {
+            if neovim {
+                *query = query::process(query)?;
+            }
+        }
     }
 
     let mut highlighter = Highlighter::new();
@@ -125,166 +279,440 @@ pub fn highlight(
         &settings.injection_query,
         &settings.locals_query,
     )?;
-    highlight_config.configure(&settings.highlight_names);
 
-    let highlights = highlighter.highlight(&highlight_config, code.as_bytes(), None, |_| None)?;
+    // every capture is configured as its own highlight so that theme keys can be resolved with
+    // explicit fallback instead of tree-sitter-highlight's part matching
+    let capture_names: Vec<_> = highlight_config
+        .query
+        .capture_names()
+        .iter()
+        .filter(|name| !theme::is_ignored_capture(name))
+        .cloned()
+        .collect();
+    highlight_config.configure(&capture_names);
+    let highlight_styles: Vec<_> = capture_names
+        .iter()
+        .map(|name| {
+            theme::resolve_capture(&settings.theme, &settings.capture_aliases, name)
+                .map(|key| (key, &settings.theme[key]))
+        })
+        .collect();
+
+    // inline fragments are wrapped in a template and only the part of the snippet is written
+    let (prefix, suffix) = match &settings.template {
+        Some(template) => template.split_once(TEMPLATE_PLACEHOLDER).with_context(|| {
+            format!("inline template `{template}` does not contain `{TEMPLATE_PLACEHOLDER}`")
+        })?,
+        None => ("", ""),
+    };
+    let source = format!("{prefix}{code}{suffix}");
+    let visible = prefix.len()..prefix.len() + code.len();
+
+    let tree = parse(settings.lang, &source)?;
+    let locals_offset = settings.injection_query.len();
+    let highlights_offset = locals_offset + settings.locals_query.len();
+    let overrides = predicate_overrides(
+        &highlight_config,
+        pattern_range(&highlight_config.query, highlights_offset..usize::MAX),
+        &capture_names,
+        &tree,
+        &source,
+    );
+
+    // errors in the template are caused by the snippet if they reach into it
+    let errors: Vec<_> = syntax_errors(tree.root_node())
+        .into_iter()
+        .filter(|node| node.start_byte() <= visible.end && node.end_byte() >= visible.start)
+        .collect();
+    // the line in the file, or in the snippet if it is not taken from a file, of an offset
+    let line_at = |offset: usize| {
+        let row = source[visible.start..offset.clamp(visible.start, visible.end)]
+            .matches('\n')
+            .count();
+        match &file_lines {
+            Some(lines) => lines.get(row).copied().unwrap_or(row + 1),
+            None => row + 1,
+        }
+    };
+    // code taken from a file is checked by parsing the whole file, because cutting it into ranges
+    // and gobbling its indentation causes errors of its own
+    let (reported, error_lines) = match file {
+        Some(file) => {
+            let shown: Option<BTreeSet<_>> =
+                file_lines.as_ref().map(|lines| lines.iter().collect());
+            let lines: Vec<_> = file.lines().collect();
+            let mut reported = vec![];
+            let mut error_lines = BTreeSet::new();
+            let file_tree = parse(settings.lang, file)?;
+            for node in syntax_errors(file_tree.root_node()) {
+                let rows = node.start_position().row + 1..=node.end_position().row + 1;
+                if shown
+                    .as_ref()
+                    .is_some_and(|shown| !rows.clone().any(|row| shown.contains(&row)))
+                {
+                    continue;
+                }
+                error_lines.extend(rows);
+                let position = node.start_position();
+                let column = lines
+                    .get(position.row)
+                    .and_then(|line| line.get(..position.column))
+                    .map_or(position.column, |before| before.chars().count());
+                reported.push((syntax_problem(node), position.row + 1, column + 1));
+            }
+            (reported, Some(error_lines))
+        }
+        None => {
+            let reported = errors
+                .iter()
+                .map(|node| {
+                    let offset = node.start_byte().max(visible.start);
+                    let before = &source[visible.start..offset];
+                    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+                    let column = before[line_start..].chars().count() + 1;
+                    (syntax_problem(*node), line_at(offset), column)
+                })
+                .collect();
+            (reported, None)
+        }
+    };
+    let location = match &cli.subcommand {
+        Command::TreeSitter { file, .. } | Command::Outline { file, .. } => {
+            format!("`{}`", file.to_string_lossy())
+        }
+        _ => "inline code".to_string(),
+    };
+    for (problem, line, column) in &reported {
+        eprintln!("lirstings: {problem} in {location} at line {line}, column {column}");
+    }
+    if cli.strict && !reported.is_empty() {
+        bail!(
+            "found {} syntax error{}, failing because of `--strict`",
+            reported.len(),
+            if reported.len() == 1 { "" } else { "s" }
+        );
+    }
+    let error_style = settings.theme.get_key_value(ERROR_UNDERLINE_KEY);
+    if settings.underline_errors && error_style.is_none() {
+        eprintln!(
+            "lirstings: no theme entry `{ERROR_UNDERLINE_KEY}` for underlining syntax errors"
+        );
+    }
+    // missing nodes are empty and cannot be underlined, errors of the snippet are only underlined
+    // in lines where the file has errors as well
+    let error_ranges: Vec<_> = match (settings.underline_errors, error_style) {
+        (true, Some((key, style))) => errors
+            .iter()
+            .filter(|node| node.is_error())
+            .filter(|node| {
+                error_lines.as_ref().is_none_or(|lines| {
+                    (line_at(node.start_byte())..=line_at(node.end_byte()))
+                        .any(|line| lines.contains(&line))
+                })
+            })
+            .map(|node| (node.byte_range(), (key.as_str(), style)))
+            .collect(),
+        _ => vec![],
+    };
+
+    let palette_len = theme::rainbow_palette_len(&settings.theme);
+    if (settings.rainbow_identifiers || settings.rainbow_brackets) && palette_len == 0 {
+        eprintln!("lirstings: no theme entries `rainbow.1`, `rainbow.2`, ... for rainbow colors");
+    }
+    let mut overlays = vec![];
+    let rainbow_identifiers = match settings.rainbow_identifiers {
+        true => palette_len,
+        false => 0,
+    };
+    if settings.scope_highlights || rainbow_identifiers > 0 {
+        overlays.extend(locals::keys(
+            &highlight_config.query,
+            pattern_range(&highlight_config.query, locals_offset..highlights_offset),
+            &tree,
+            &source,
+            rainbow_identifiers,
+        ));
+    }
+    if settings.rainbow_brackets && palette_len > 0 {
+        overlays.extend(brackets::keys(&tree, palette_len));
+    }
+    overlays.sort_by_key(|(range, _)| range.start);
+    let overlays: Vec<_> = overlays
+        .into_iter()
+        .filter_map(|(range, key)| {
+            let key = theme::resolve_capture(&settings.theme, &settings.capture_aliases, &key)?;
+            Some((range, (key, &settings.theme[key])))
+        })
+        .collect();
+
+    let events = highlighter
+        .highlight(&highlight_config, source.as_bytes(), None, |_| None)?
+        .collect::<Result<Vec<_>, _>>()?;
+    let ranges = highlight_ranges(&events);
     let mut style_stack = vec![];
-    for event in highlights {
-        match event? {
-            HighlightEvent::HighlightStart(Highlight(highlight)) => style_stack.push(highlight),
+    let mut unstyled_captures = BTreeSet::new();
+    let mut next_overlay = 0;
+    let mut next_error = 0;
+    for (event, range) in events.into_iter().zip(ranges) {
+        match event {
+            HighlightEvent::HighlightStart(Highlight(highlight)) => style_stack.push(
+                overrides
+                    .get(&(highlight, range))
+                    .copied()
+                    .unwrap_or(Some(highlight)),
+            ),
             HighlightEvent::HighlightEnd => {
                 style_stack.pop();
             }
-            HighlightEvent::Source { start, end } => match style_stack.last() {
-                Some(highlight) => {
-                    output.push_str(&settings.highlight_styles[*highlight].write(&code[start..end]))
+            HighlightEvent::Source { start, end } => {
+                let mut start = start.max(visible.start);
+                let end = end.min(visible.end);
+                if start >= end {
+                    continue;
+                }
+                if let Some(Some(highlight)) = style_stack.last() {
+                    if highlight_styles[*highlight].is_none() {
+                        unstyled_captures.insert(capture_names[*highlight].as_str());
+                    }
                 }
-                None => output.push_str(&code[start..end].replace('{', "×{").replace('}', "×}")),
-            },
+                // captures without a theme entry do not override the enclosing ones
+                let style = style_stack.iter().rev().find_map(|highlight| {
+                    highlight.and_then(|highlight| highlight_styles[highlight])
+                });
+                // overlays take precedence over the highlights of the parts they cover, errors
+                // are layered on top of both
+                while start < end {
+                    let (overlay, overlay_end) = range_at(&overlays, &mut next_overlay, start);
+                    let (error, error_end) = range_at(&error_ranges, &mut next_error, start);
+                    let segment_end = end.min(overlay_end).min(error_end);
+                    output.push_str(&write_segment(
+                        &source[start..segment_end],
+                        overlay.copied().or(style),
+                        error.copied(),
+                        settings.semantic_styles,
+                    ));
+                    start = segment_end;
+                }
+            }
         }
     }
 
+    if !unstyled_captures.is_empty() {
+        eprintln!(
+            "lirstings: no theme entry for captures: {}",
+            unstyled_captures
+                .into_iter()
+                .map(|name| format!("@{name}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
     Ok(output.finish())
 }
 
-fn process_queries(lang: Language, source: &str) -> Result<String> {
-    let query = Query::new(lang, source)?;
-    let start_bytes: Vec<_> = (0..query.pattern_count())
-        .map(|index| {
-            (
-                query.start_byte_for_pattern(index),
-                query
-                    .general_predicates(index)
-                    .iter()
-                    .filter_map(|predicate| match predicate.operator.as_ref() {
-                        "lua-match?" => Some((
-                            "#lua-match?",
-                            (
-                                "#match?",
-                                vec![
-                                    clone_predicate_arg(&predicate.args[0]),
-                                    QueryPredicateArg::String(match &predicate.args[1] {
-                                        QueryPredicateArg::String(str) => {
-                                            str.replace('\\', r"\\")
-                                                .replace("%.", r"\.")
-                                                .replace("%%", r"%")
-                                                .replace("%a", r"[a-zA-Z]")
-                                                .replace("%A", r"[^a-zA-Z]")
-                                                .replace("%c", r"[\0-\31]")
-                                                .replace("%C", r"[^\0-\31]")
-                                                .replace("%d", r"[0-9]")
-                                                .replace("%D", r"[^0-9]")
-                                                .replace("%g", r"[\33-\126]")
-                                                .replace("%G", r"[^\33-\126]")
-                                                .replace("%l", r"[a-z]")
-                                                .replace("%L", r"[^a-z]")
-                                                .replace("%p", r##"[!"#$%&'()*+,\-./:;<=>?@[\\\]^_`{|}~]"##)
-                                                .replace("%P", r##"[^!"#$%&'()*+,\-./:;<=>?@[\\\]^_`{|}~]"##)
-                                                .replace("%s", r"[ \t\n\v\f\r]")
-                                                .replace("%S", r"[^ \t\n\v\f\r]")
-                                                .replace("%u", r"[A-Z]")
-                                                .replace("%U", r"[^A-Z]")
-                                                .replace("%w", r"[a-zA-Z0-9]")
-                                                .replace("%W", r"[^a-zA-Z0-9]")
-                                                .replace("%x", r"[0-9a-fA-F]")
-                                                .replace("%X", r"[^0-9a-fA-F]")
-                                                .replace("%z", r"\0")
-                                                .replace("%Z", r"[^\0]")
-                                                .into_boxed_str()
-                                        }
-                                        _ => panic!("second arg to #lua-match? must be string"),
-                                    }),
-                                ],
-                            ),
-                        )),
-                        "any-of?" => Some((
-                            "#any-of?",
-                            (
-                                "#match?",
-                                vec![
-                                    clone_predicate_arg(&predicate.args[0]),
-                                    QueryPredicateArg::String(
-                                        format!(
-                                            "^({})$",
-                                            predicate.args[1..]
-                                                .iter()
-                                                .map(|arg| match arg {
-                                                    QueryPredicateArg::String(str) => str.as_ref(),
-                                                    _ => panic!("args to #any-of? must be strings"),
-                                                })
-                                                .collect::<Vec<_>>()
-                                                .join("|")
-                                        )
-                                        .into_boxed_str(),
-                                    ),
-                                ],
-                            ),
-                        )),
-                        "contains?" => Some((
-                            "#contains?",
-                            (
-                                "#match?",
-                                vec![
-                                    clone_predicate_arg(&predicate.args[0]),
-                                    clone_predicate_arg(&predicate.args[1]),
-                                ],
-                            ),
-                        )),
-                        _ => None,
-                    })
-                    .collect::<HashMap<_, _>>(),
-            )
-        })
-        .collect();
-    let queries: String = start_bytes
-        .iter()
-        .enumerate()
-        .map(|(index, (start, predicate_replacements))| {
-            let mut q = match start_bytes.get(index + 1) {
-                Some((end, _)) => &source[*start..*end],
-                None => &source[*start..],
+type Style<'theme> = (&'theme str, &'theme ThemeValue);
+
+/// Writes text in the style of a theme entry, with the style of the `error.underline` entry
+/// layered on top if it is part of a syntax error
+fn write_segment(
+    text: &str,
+    style: Option<Style>,
+    error_style: Option<Style>,
+    semantic_styles: bool,
+) -> String {
+    match (style, error_style) {
+        (Some((key, _)), None) if semantic_styles => theme::write_semantic(key, text),
+        (Some((_, style)), None) => style.write(text),
+        (None, None) => text.replace('{', "×{").replace('}', "×}"),
+        (style, Some((error_key, _))) if semantic_styles => {
+            let lines: Vec<_> = write_segment(text, style, None, true)
+                .lines()
+                .map(|line| format!("×LirstStyle{{{error_key}}}{{{line}}}"))
+                .collect();
+            lines.join("\n")
+        }
+        (Some((_, style)), Some((_, error @ ThemeValue::Object(_)))) => {
+            error.linked_to(style).write(text)
+        }
+        (_, Some((_, error))) => error.write(text),
+    }
+}
+
+/// The value of the range in `ranges` containing `offset` and the offset at which that changes.
+/// `ranges` are sorted and `next` is the index of the first one that may contain `offset`, which
+/// is advanced for increasing offsets.
+fn range_at<'ranges, T>(
+    ranges: &'ranges [(Range<usize>, T)],
+    next: &mut usize,
+    offset: usize,
+) -> (Option<&'ranges T>, usize) {
+    while ranges
+        .get(*next)
+        .is_some_and(|(range, _)| range.end <= offset)
+    {
+        *next += 1;
+    }
+    match ranges.get(*next) {
+        Some((range, value)) if range.start <= offset => (Some(value), range.end),
+        Some((range, _)) => (None, range.start),
+        None => (None, usize::MAX),
+    }
+}
+
+fn syntax_problem(node: Node) -> String {
+    match node.is_missing() {
+        true => format!("missing `{}`", node.kind()),
+        false => "syntax error".to_string(),
+    }
+}
+
+/// ERROR and MISSING nodes, without the nodes inside of ERROR nodes
+fn syntax_errors(node: Node) -> Vec<Node> {
+    if node.is_error() || node.is_missing() {
+        return vec![node];
+    }
+    if !node.has_error() {
+        return vec![];
+    }
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.children(&mut cursor).collect();
+    children.into_iter().flat_map(syntax_errors).collect()
+}
+
+pub fn parse(lang: Language, code: &str) -> Result<Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(lang)?;
+    parser.parse(code, None).context("could not parse the code")
+}
+
+/// The byte range of every `HighlightStart` event, and an empty range for all other events
+fn highlight_ranges(events: &[HighlightEvent]) -> Vec<(usize, usize)> {
+    let mut ranges = vec![(0, 0); events.len()];
+    let mut open = vec![];
+    let mut offset = 0;
+    for (index, event) in events.iter().enumerate() {
+        match event {
+            HighlightEvent::HighlightStart(_) => {
+                ranges[index].0 = offset;
+                open.push(index);
             }
-            .to_string();
-            for (predicate, replacement) in predicate_replacements {
-                q = q.replace(
-                    &format!(
-                        "{}{}",
-                        predicate,
-                        q.split_once(predicate)
-                            .expect("replacements are correctly added above")
-                            .1
-                            .split_once(')')
-                            .expect("replacements are correctly added above")
-                            .0
-                    ),
-                    &format!(
-                        "{} {}",
-                        replacement.0,
-                        display_predicate_args(&query, &replacement.1)
-                    ),
-                );
+            HighlightEvent::HighlightEnd => {
+                if let Some(start) = open.pop() {
+                    ranges[start].1 = offset;
+                }
             }
-            q
-        })
-        .rev()
-        .collect();
-    Ok(queries)
+            HighlightEvent::Source { end, .. } => offset = *end,
+        }
+    }
+    ranges
 }
 
-fn clone_predicate_arg(arg: &QueryPredicateArg) -> QueryPredicateArg {
-    match arg {
-        QueryPredicateArg::Capture(num) => QueryPredicateArg::Capture(*num),
-        QueryPredicateArg::String(str) => QueryPredicateArg::String(str.clone()),
+/// Replacements for highlights assigned by tree-sitter-highlight, keyed by the highlight and the
+/// byte range of its node
+type Overrides = HashMap<(usize, (usize, usize)), Option<usize>>;
+
+/// tree-sitter-highlight ignores the predicates in `query::RUNTIME_PREDICATES`, so every pattern
+/// using them matches unconditionally. This evaluates them with a separate query cursor and maps
+/// each highlight that was wrongly assigned to a node to the highlight of the first pattern that
+/// actually matches it, or to `None` if there is no such pattern.
+fn predicate_overrides(
+    config: &HighlightConfiguration,
+    highlight_patterns: Range<usize>,
+    capture_names: &[String],
+    tree: &Tree,
+    code: &str,
+) -> Overrides {
+    let query = &config.query;
+    let mut overrides = HashMap::new();
+    let uses_runtime_predicates = highlight_patterns.clone().any(|pattern| {
+        query
+            .general_predicates(pattern)
+            .iter()
+            .any(|predicate| query::RUNTIME_PREDICATES.contains(&predicate.operator.as_ref()))
+    });
+    if !uses_runtime_predicates {
+        return overrides;
+    }
+
+    // the pattern, highlight and whether the pattern holds for every capture of a node
+    let mut captures = HashMap::<_, Vec<(usize, Option<usize>, bool)>>::new();
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(query, tree.root_node(), code.as_bytes()) {
+        if !highlight_patterns.contains(&query_match.pattern_index) {
+            continue;
+        }
+        let holds = query::satisfies_runtime_predicates(query, &query_match);
+        for capture in query_match.captures {
+            let name = &query.capture_names()[capture.index as usize];
+            captures
+                .entry((capture.node.start_byte(), capture.node.end_byte()))
+                .or_default()
+                .push((
+                    query_match.pattern_index,
+                    capture_names.iter().position(|n| n == name),
+                    holds,
+                ));
+        }
+    }
+    for (range, mut captures) in captures {
+        // like tree-sitter-highlight, only the first pattern matching a node is used
+        captures.sort_by_key(|(pattern, ..)| *pattern);
+        if let (_, Some(highlight), false) = captures[0] {
+            let replacement = captures
+                .iter()
+                .find(|(_, _, holds)| *holds)
+                .and_then(|(_, highlight, _)| *highlight);
+            overrides.insert((highlight, range), replacement);
+        }
     }
+    overrides
 }
 
-fn display_predicate_args(query: &Query, args: &[QueryPredicateArg]) -> String {
-    args.iter()
-        .map(|arg| match arg {
-            QueryPredicateArg::Capture(num) => {
-                format!("@{}", query.capture_names()[*num as usize])
-            }
-            QueryPredicateArg::String(str) => format!("\"{str}\""),
-        } + " ")
-        .collect()
+/// The indices of the patterns of the combined query of a `HighlightConfiguration` which come
+/// from the part of the query source in `bytes`
+fn pattern_range(query: &Query, bytes: Range<usize>) -> Range<usize> {
+    let first_at = |offset| {
+        (0..query.pattern_count())
+            .find(|pattern| query.start_byte_for_pattern(*pattern) >= offset)
+            .unwrap_or(query.pattern_count())
+    };
+    first_at(bytes.start)..first_at(bytes.end)
+}
+
+// the tests need a grammar, which is only available without setup when it is bundled
+#[cfg(all(test, feature = "bundled-grammars"))]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// Settings with a theme giving each capture in `captures` its own color, `#000001` and so on
+    fn settings(file_name: &str, captures: &[&str]) -> Settings {
+        let theme: serde_json::Map<_, _> = captures
+            .iter()
+            .enumerate()
+            .map(|(index, capture)| (capture.to_string(), format!("#{:06}", index + 1).into()))
+            .collect();
+        let config = serde_json::from_value(serde_json::json!({ "theme": theme })).unwrap();
+        settings_for_file(config, Path::new(file_name)).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_captures_of_bundled_queries() {
+        let settings = settings("file.rs", &["constructor", "function"]);
+        let cli = Cli::parse_from(["lirstings", "ts", "file.rs"]);
+        let out = highlight("let y = Some(x);", None, None, &cli, settings, None).unwrap();
+        assert!(out.contains("{000001}{Some}"), "{out}");
+    }
+
+    #[test]
+    fn parses_inline_code_as_it_is_without_a_context() {
+        let config: Config = serde_json::from_str(include_str!("default_config.json")).unwrap();
+        let cli = Cli::parse_from(["lirstings", "--strict", "inline", "go", "func main() {}"]);
+        let settings = get_settings(config, &cli.subcommand).unwrap();
+        assert_eq!(settings.template, None);
+        let out = highlight("func main() {}", None, None, &cli, settings, None).unwrap();
+        assert!(out.contains("{main}"), "{out}");
+    }
 }